@@ -0,0 +1,283 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::bees::{Bee, BeeBehavior, Team};
+use crate::behavior::BeeState;
+use crate::boids::Position;
+use crate::fog::{FogOfWar, FogState};
+use crate::caste::{Caste, Castes};
+use crate::hive::Hive;
+use crate::queen::{min_hive_distance, Queen, FOUNDING_COST};
+use crate::tilemap::Flower;
+
+// How hard the computer controlled teams play, picked in the menu before the level is generated
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn next(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Easy,
+        }
+    }
+
+    // Seconds between two decisions of an AI team
+    pub fn think_interval(self) -> f32 {
+        match self {
+            Difficulty::Easy => 3.0,
+            Difficulty::Normal => 1.5,
+            Difficulty::Hard => 0.75,
+        }
+    }
+
+    // Honey the AI hive starts out with
+    pub fn head_start(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.0,
+            Difficulty::Normal => 40.0,
+            Difficulty::Hard => 120.0,
+        }
+    }
+
+    // Bees the AI wants before it considers attacking
+    pub fn attack_threshold(self) -> usize {
+        match self {
+            Difficulty::Easy => 25,
+            Difficulty::Normal => 15,
+            Difficulty::Hard => 10,
+        }
+    }
+
+    pub fn max_hives(self) -> usize {
+        match self {
+            Difficulty::Easy => 1,
+            Difficulty::Normal => 2,
+            Difficulty::Hard => 4,
+        }
+    }
+}
+
+// Everything an AI team can choose to do in one decision
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AiAction {
+    Forage,  // Send idle bees to the closest known flowers
+    Explore, // Send idle bees out to find flowers
+    Grow,    // Spend honey on new bees
+    Expand,  // Walk the queen out to found another hive
+    Attack,  // Throw the swarm at the closest known enemy hive or queen
+}
+
+// What an AI team knows about the world when it decides, gathered once per decision
+struct AiView {
+    bees: Vec<(Entity, Vec2, BeeState)>,
+    honey: f32,
+    hives: Vec<Vec2>,
+    queen: Option<(Entity, Vec2)>,
+    flowers: Vec<(Entity, Vec2)>,
+    targets: Vec<(Entity, Vec2)>, // Enemy hives and queens it has spotted
+}
+
+impl AiView {
+    fn empty() -> Self {
+        AiView {
+            bees: Vec::new(),
+            honey: 0.0,
+            hives: Vec::new(),
+            queen: None,
+            flowers: Vec::new(),
+            targets: Vec::new(),
+        }
+    }
+
+    // Mostly workers to keep the honey coming, every third bee a soldier for the attack
+    fn next_caste(&self) -> Caste {
+        if self.bees.len() % 3 == 2 {
+            Caste::Soldier
+        } else {
+            Caste::Worker
+        }
+    }
+
+    fn idle(&self) -> usize {
+        self.bees.iter().filter(|(_, _, state)| *state == BeeState::Idle).count()
+    }
+
+    // Utility of every action between 0 and 1, the highest one gets done
+    fn score(&self, action: AiAction, difficulty: Difficulty, castes: &Castes) -> f32 {
+        let bee_cost = castes.get(self.next_caste()).cost;
+        let bees = self.bees.len().max(1) as f32;
+        let idle = self.idle() as f32 / bees;
+        match action {
+            AiAction::Forage if self.flowers.is_empty() => 0.0,
+            AiAction::Forage => idle,
+            AiAction::Explore if self.flowers.is_empty() => idle.max(0.2),
+            AiAction::Explore => idle * 0.3,
+            AiAction::Grow if self.honey < bee_cost => 0.0,
+            AiAction::Grow => (1.0 - bees / difficulty.attack_threshold() as f32).clamp(0.2, 0.9),
+            AiAction::Expand => {
+                let affordable = self.honey >= FOUNDING_COST + bee_cost;
+                if !affordable || self.queen.is_none() || self.hives.len() >= difficulty.max_hives() {
+                    0.0
+                } else {
+                    0.5
+                }
+            }
+            AiAction::Attack if self.targets.is_empty() => 0.0,
+            AiAction::Attack => (bees / difficulty.attack_threshold() as f32 - 0.5).clamp(0.0, 1.0),
+        }
+    }
+}
+
+const ACTIONS: [AiAction; 5] = [
+    AiAction::Forage,
+    AiAction::Explore,
+    AiAction::Grow,
+    AiAction::Expand,
+    AiAction::Attack,
+];
+
+// Every team but the player's is run by a utility AI: every `think_interval` it scores each `AiAction`
+// against what it can see through its own fog of war and carries out the best one
+pub fn run_ai(
+    mut bees: Query<(Entity, &Position, &Team, &mut BeeBehavior), With<Bee>>,
+    mut hives: Query<(Entity, &Transform, &Team, &mut Hive)>,
+    mut queens: Query<(Entity, &Position, &Team, &mut Queen)>,
+    flowers: Query<(Entity, &Transform), With<Flower>>,
+    fog: Res<FogOfWar>,
+    difficulty: Res<Difficulty>,
+    castes: Res<Castes>,
+    time: Res<Time<Fixed>>,
+    mut since_last: Local<f32>,
+) {
+    *since_last += time.delta_seconds();
+    if *since_last < difficulty.think_interval() {
+        return;
+    }
+    *since_last = 0.0;
+
+    let mut views: HashMap<u32, AiView> = HashMap::new();
+    for (entity, position, team, _) in queens.iter() {
+        if *team != Team::PLAYER {
+            views.entry(team.0).or_insert_with(AiView::empty).queen = Some((entity, position.0.truncate()));
+        }
+    }
+    for (_, transform, team, hive) in hives.iter() {
+        if *team != Team::PLAYER {
+            let view = views.entry(team.0).or_insert_with(AiView::empty);
+            view.honey += hive.honey;
+            view.hives.push(transform.translation.truncate());
+        }
+    }
+    for (entity, position, team, behavior) in bees.iter() {
+        if let Some(view) = views.get_mut(&team.0) {
+            view.bees.push((entity, position.0.truncate(), behavior.state()));
+        }
+    }
+    for (team, view) in views.iter_mut() {
+        let team = Team(*team);
+        // The AI plays fair: flowers and enemies only count once its own bees have seen them
+        view.flowers = flowers
+            .iter()
+            .map(|(flower, transform)| (flower, transform.translation.truncate()))
+            .filter(|(_, position)| fog.state(team, *position) != FogState::Unexplored)
+            .collect();
+        view.targets = hives
+            .iter()
+            .map(|(hive, transform, hive_team, _)| (hive, transform.translation.truncate(), *hive_team))
+            .chain(queens.iter().map(|(queen, position, queen_team, _)| (queen, position.0.truncate(), *queen_team)))
+            .filter(|(_, position, other)| *other != team && fog.is_visible(team, *position))
+            .map(|(target, position, _)| (target, position))
+            .collect();
+    }
+
+    for (team, view) in views.iter() {
+        let team = Team(*team);
+        let Some(action) = ACTIONS
+            .iter()
+            .copied()
+            .map(|action| (action, view.score(action, *difficulty, &castes)))
+            .filter(|(_, score)| *score > 0.0)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(action, _)| action)
+        else {
+            continue;
+        };
+        match action {
+            AiAction::Forage => {
+                for (bee, position, state) in view.bees.iter() {
+                    if *state != BeeState::Idle {
+                        continue;
+                    }
+                    let closest = view.flowers.iter().min_by(|(_, a), (_, b)| {
+                        a.distance_squared(*position).total_cmp(&b.distance_squared(*position))
+                    });
+                    if let (Some((flower, _)), Ok((_, _, _, mut behavior))) = (closest, bees.get_mut(*bee)) {
+                        *behavior = BeeBehavior::Foraging { flower: *flower };
+                    }
+                }
+            }
+            AiAction::Explore => {
+                for (index, (bee, position, state)) in view.bees.iter().enumerate() {
+                    if *state != BeeState::Idle {
+                        continue;
+                    }
+                    if let Ok((_, _, _, mut behavior)) = bees.get_mut(*bee) {
+                        // Spread out in different directions, `explore_fog` takes it from there
+                        let wander = index as f32 * std::f32::consts::TAU / view.bees.len() as f32;
+                        *behavior = BeeBehavior::Exploring { origin: *position, wander };
+                    }
+                }
+            }
+            AiAction::Grow => {
+                let richest = hives
+                    .iter_mut()
+                    .filter(|(_, _, hive_team, _)| **hive_team == team)
+                    .max_by(|(_, _, _, a), (_, _, _, b)| a.honey.total_cmp(&b.honey));
+                if let Some((_, _, _, mut hive)) = richest {
+                    hive.queue_bee(view.next_caste(), &castes);
+                }
+            }
+            AiAction::Expand => {
+                // Settle next to the flower furthest from every hive of the team, but not on top of anyone
+                let min_distance = min_hive_distance(&fog.level);
+                let spot = view
+                    .flowers
+                    .iter()
+                    .map(|(_, position)| *position)
+                    .filter(|position| view.hives.iter().all(|hive| hive.distance(*position) >= min_distance))
+                    .max_by(|a, b| {
+                        let nearest = |spot: &Vec2| view.hives.iter().map(|hive| hive.distance(*spot)).fold(f32::MAX, f32::min);
+                        nearest(a).total_cmp(&nearest(b))
+                    });
+                if let (Some(spot), Some((queen_entity, _))) = (spot, view.queen) {
+                    if let Ok((_, _, _, mut queen)) = queens.get_mut(queen_entity) {
+                        queen.destination = Some(spot);
+                        queen.founding = true;
+                    }
+                }
+            }
+            AiAction::Attack => {
+                // Everyone not carrying honey home goes
+                for (bee, position, state) in view.bees.iter() {
+                    if *state == BeeState::Returning {
+                        continue;
+                    }
+                    let closest = view.targets.iter().min_by(|(_, a), (_, b)| {
+                        a.distance_squared(*position).total_cmp(&b.distance_squared(*position))
+                    });
+                    if let (Some((target, _)), Ok((_, _, _, mut behavior))) = (closest, bees.get_mut(*bee)) {
+                        *behavior = BeeBehavior::Attacking(*target);
+                    }
+                }
+            }
+        }
+        debug!("Team {} decided to {:?}", team.0, action);
+    }
+}
@@ -1,41 +1,40 @@
-use bevy::time::common_conditions::on_timer;
 use bevy_inspector_egui::prelude::ReflectInspectorOptions;
 use bevy_inspector_egui::InspectorOptions;
 use quadtree::prelude::*;
 use quadtree::quadtree::tree::QuadTree;
 use rand::Rng;
-use std::time::Duration;
 
-use crate::boids::{create_boid_group, move_system};
 // use crate::world::Queen;
+pub use crate::behavior::BeeBehavior;
+use crate::behavior::{BeeState, LastBeeState};
+use crate::caste::{Caste, Castes};
+use crate::combat::{Attack, Health};
+use crate::economy::Cargo;
+use crate::fog::{Exploration, RevealRadius};
+use crate::navigation::FlightPath;
+use crate::orders::Orders;
 use crate::{
-    boids::{build_or_update_quadtree, update_boids, Boid},
+    boids::{interpolate_transforms, Boid, Position, PreviousPosition},
     loading::TextureAssets,
     GameState,
 };
 
-use crate::interactions::{MousePosition, Highlightable};
+use crate::interactions::Highlightable;
 use bevy::prelude::*;
-use crate::tilemap::FogTile;
 // use bevy::window::PrimaryWindow;
 
+/// This plugin handles the player facing side of the bees: sprites and wing animation
+/// New bees are hatched by their `Hive`
+/// The flocking itself is run by the `SimulationPlugin`
 pub struct BeesPlugin;
 
 impl Plugin for BeesPlugin {
     fn build(&self, app: &mut App) {
         app
             .add_systems(OnEnter(GameState::Playing), setup)
-            .add_systems(Update, create_boid_group.run_if(in_state(GameState::Playing)))
-            .add_systems(Update, place_bee.run_if(in_state(GameState::Playing)))
+            .add_systems(Update, attach_bee_sprites.run_if(in_state(GameState::Playing)))
             .add_systems(Update, animate_wings.run_if(in_state(GameState::Playing)))
-            // .add_systems(Update, clear_fog.run_if(in_state(GameState::Playing)))
-            .add_systems(
-                Update,
-                (build_or_update_quadtree, update_boids, move_system).run_if(
-                    in_state(GameState::Playing)
-                        .and_then(on_timer(Duration::from_secs_f32(1. / 90.))),
-                ),
-            )
+            .add_systems(Update, interpolate_transforms.run_if(in_state(GameState::Playing)))
         ;
     }
 }
@@ -65,25 +64,101 @@ impl Collider {
 pub struct Velocity(pub Vec3);
 
 impl Velocity {
-    pub fn default() -> Self {
-        let mut rng = rand::thread_rng();
-        Velocity(Vec3::new(
+    pub fn random(rng: &mut impl Rng) -> Self {
+        let direction = Vec3::new(
             rng.gen_range(-1.0..1.0),
             rng.gen_range(-1.0..1.0),
             0.0,
-        ))
+        );
+        Velocity(direction.try_normalize().unwrap_or(Vec3::Y))
     }
 }
 
 #[derive(Component)]
 pub struct Bee;
 
-#[derive(Component)]
-pub enum BeeBehavior {
-    // Traveling(Vec2), // Destination coordinates
-    Destination(Vec2), // Point of origin coordinates
-    // Exploring(Vec2), // Point of origin coordinates
-    // Interacting(Vec2), // Coordinates of interactable object
+// Only the simulation side of a bee, sprites are attached in `attach_bee_sprites`
+#[derive(Bundle)]
+pub struct BeeBundle {
+    pub transform: TransformBundle,
+    pub position: Position,
+    pub previous_position: PreviousPosition,
+    pub bee: Bee,
+    pub behavior: BeeBehavior,
+    pub last_state: LastBeeState,
+    pub boid: Boid,
+    pub team: Team,
+    pub highlightable: Highlightable,
+    pub collider: Collider,
+    pub velocity: Velocity,
+    pub steering: Steering,
+    pub reveal: RevealRadius,
+    pub exploration: Exploration,
+    pub cargo: Cargo,
+    pub health: Health,
+    pub attack: Attack,
+    pub caste: Caste,
+    pub orders: Orders,
+    pub path: FlightPath,
+    pub variant: BeeVariant,
+}
+
+impl BeeBundle {
+    pub fn new(position: Vec3, destination: Vec2, team: Team, caste: Caste, castes: &Castes, rng: &mut impl Rng) -> Self {
+        let stats = castes.get(caste);
+        BeeBundle {
+            transform: TransformBundle::from_transform(Transform::from_translation(position)),
+            position: Position(position),
+            previous_position: PreviousPosition(position),
+            bee: Bee,
+            behavior: BeeBehavior::Traveling(destination),
+            last_state: LastBeeState(BeeState::Traveling),
+            boid: Boid,
+            team,
+            highlightable: Highlightable,
+            collider: Collider::new(stats.collider),
+            velocity: Velocity::random(rng),
+            steering: Steering::default(),
+            reveal: RevealRadius(stats.reveal),
+            exploration: Exploration::default(),
+            cargo: Cargo::new(stats.capacity),
+            health: Health::new(stats.health),
+            attack: Attack::new(stats.damage, 1.0),
+            caste,
+            orders: Orders::default(),
+            path: FlightPath::default(),
+            variant: stats.sprite,
+        }
+    }
+}
+
+// Which body and wing textures a bee is drawn with and how big, set by its `Caste`
+#[derive(Component, Clone, Copy, Debug)]
+pub struct BeeVariant {
+    pub body: u8,
+    pub wings: u8,
+    pub scale: f32,
+}
+
+// Everything other than flocking that wants to move a boid this tick, blended in and reset by `update_boids`
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Steering {
+    pub force: Vec3,
+    pub throttle: f32, // Fraction of the group speed to fly at, lowered when arriving somewhere
+    pub flocking: f32, // How much the boid listens to the group's cohesion and alignment right now
+    pub separation: f32, // and to its separation, kept up in formations that ignore the rest
+}
+
+impl Default for Steering {
+    fn default() -> Self {
+        Steering { force: Vec3::ZERO, throttle: 1.0, flocking: 1.0, separation: 1.0 }
+    }
+}
+
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BeeArrived {
+    pub entity: Entity,
+    pub position: Vec2,
 }
 
 // Separate out these types of data???
@@ -101,10 +176,45 @@ pub struct BoidGroup {
     pub alignment: f32,
     #[inspector(min = 0.0, max = 1.0)]
     pub cohesion: f32,
+    #[inspector(min = 0.0, max = 1.0)]
+    pub avoidance: f32, // Steering away from boids of other teams
+    #[inspector(min = 0.0, max = 2.0)]
+    pub obstacle_avoidance: f32, // Steering around walls, hives and other static obstacles
+    #[inspector(min = 0.0, max = 2000.0)]
+    pub look_ahead: f32, // How far ahead along its heading a boid looks out for obstacles
+    #[inspector(min = 0.0, max = 1000.0)]
+    pub separation_radius: f32, // Neighbors closer than this push each other away
+    #[inspector(min = 0.0, max = 2.0)]
+    pub max_force: f32, // Upper bound on how much the heading can change in one tick
+    #[inspector(min = 0.0, max = 2.0)]
+    pub seek: f32, // Pull towards a bee's destination
+    #[inspector(min = 0.0, max = 2000.0)]
+    pub slowing_radius: f32, // Bees start slowing down this close to their destination
+    #[inspector(min = 0.0, max = 500.0)]
+    pub arrive_radius: f32, // and have arrived this close to it
+    #[inspector(min = 0.0, max = 2.0)]
+    pub exploration: f32, // Pull of exploring bees towards unexplored tiles
+    #[inspector(min = 0.0, max = 20000.0)]
+    pub exploration_range: f32, // How far around itself an exploring bee looks for unexplored tiles
     #[inspector(min = 0.0, max = 200.0)]
     pub speed: f32,
     #[inspector(min = 0.0, max = 1000.0)]
     pub vision: f32,
+    #[inspector(min = 0.0, max = 360.0)]
+    pub fov: f32, // Full angle of the vision cone in degrees, centered on the heading
+    pub bounds: Rect, // The part of the world the group lives in, same as its quadtree region
+    pub border: BorderMode,
+    #[inspector(min = 0.0, max = 2000.0)]
+    pub border_margin: f32, // How far from the edge `BorderMode::Steer` starts turning boids around
+}
+
+// What happens to a boid reaching the edge of its group's bounds
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BorderMode {
+    #[default]
+    Steer, // Soft force back inside, growing the closer the boid gets to the edge
+    Reflect, // Bounce off the edge
+    Wrap, // Leave on one side, come back in on the other
 }
 
 impl Default for BoidGroup {
@@ -121,24 +231,143 @@ impl Default for BoidGroup {
 
 impl BoidGroup {
     pub fn new(min: Vec2, max: Vec2, team: Team) -> Self {
-        let min = coord::Coord::from_f32(min.x, min.y);
-        let max = coord::Coord::from_f32(max.x, max.y);
         BoidGroup {
-            graph: QuadTree::new(region::Region::new(min, max)),
+            graph: QuadTree::new(region::Region::new(
+                coord::Coord::from_f32(min.x, min.y),
+                coord::Coord::from_f32(max.x, max.y),
+            )),
             id: team.0,
             count: 0,
             separation: 0.5,
             alignment: 0.3,
             cohesion: 0.3,
+            avoidance: 0.6,
+            obstacle_avoidance: 1.0,
+            look_ahead: 600.0,
+            separation_radius: 150.0,
+            max_force: 0.2,
+            seek: 0.8,
+            slowing_radius: 600.0,
+            arrive_radius: 100.0,
+            exploration: 0.7,
+            exploration_range: 5000.0,
             speed: 240.0,
             vision: 600.0,
+            fov: 270.0,
+            bounds: Rect::from_corners(min, max),
+            border: BorderMode::default(),
+            border_margin: 500.0,
+        }
+    }
+
+    // Resize the group, e.g. when a new level is loaded. The quadtree is rebuilt empty
+    pub fn set_bounds(&mut self, min: Vec2, max: Vec2) {
+        self.graph = QuadTree::new(region::Region::new(
+            coord::Coord::from_f32(min.x, min.y),
+            coord::Coord::from_f32(max.x, max.y),
+        ));
+        self.bounds = Rect::from_corners(min, max);
+    }
+
+    // Force turning a boid back inside the bounds, only for `BorderMode::Steer`
+    // Zero until the boid is within `border_margin` of an edge, 1 at the edge and more beyond it
+    pub fn border_steering(&self, position: Vec3) -> Vec3 {
+        if self.border != BorderMode::Steer {
+            return Vec3::ZERO;
+        }
+        let margin = self.border_margin.max(1.0);
+        let (min, max) = (self.bounds.min + margin, self.bounds.max - margin);
+        let mut force = Vec2::ZERO;
+        if position.x < min.x {
+            force.x += (min.x - position.x) / margin;
+        }
+        if position.x > max.x {
+            force.x -= (position.x - max.x) / margin;
+        }
+        if position.y < min.y {
+            force.y += (min.y - position.y) / margin;
+        }
+        if position.y > max.y {
+            force.y -= (position.y - max.y) / margin;
+        }
+        force.extend(0.0)
+    }
+
+    // Puts a boid that moved out of the bounds back in for the hard border modes
+    // Returns how far it was teleported, which is only ever non zero when wrapping around
+    pub fn confine(&self, position: &mut Vec3, velocity: &mut Vec3) -> Vec3 {
+        let before = *position;
+        let (min, max) = (self.bounds.min, self.bounds.max);
+        match self.border {
+            BorderMode::Steer => {}
+            BorderMode::Reflect => {
+                if position.x < min.x {
+                    position.x = 2.0 * min.x - position.x;
+                    velocity.x = velocity.x.abs();
+                }
+                if position.x > max.x {
+                    position.x = 2.0 * max.x - position.x;
+                    velocity.x = -velocity.x.abs();
+                }
+                if position.y < min.y {
+                    position.y = 2.0 * min.y - position.y;
+                    velocity.y = velocity.y.abs();
+                }
+                if position.y > max.y {
+                    position.y = 2.0 * max.y - position.y;
+                    velocity.y = -velocity.y.abs();
+                }
+                return Vec3::ZERO;
+            }
+            BorderMode::Wrap => {
+                let size = self.bounds.size();
+                if size.x > 0.0 {
+                    position.x = min.x + (position.x - min.x).rem_euclid(size.x);
+                }
+                if size.y > 0.0 {
+                    position.y = min.y + (position.y - min.y).rem_euclid(size.y);
+                }
+            }
+        }
+        *position - before
+    }
+
+    // Whether a boid at `position` heading along `heading` sees `other`: within `vision` and inside the cone
+    // `vision` is how far this particular boid sees, the group's vision scaled by its caste
+    pub fn can_see(&self, position: Vec3, heading: Vec3, other: Vec3, vision: f32) -> bool {
+        let offset = other - position;
+        if offset.length_squared() > vision * vision {
+            return false;
+        }
+        match (heading.try_normalize(), offset.try_normalize()) {
+            (Some(heading), Some(offset)) => {
+                heading.dot(offset) >= (self.fov.to_radians() / 2.0).cos()
+            }
+            // Standing still or exactly on top of each other, nothing to tell the direction from
+            _ => true,
         }
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct Team(pub u32);
 
+impl Team {
+    // The team of the person playing, whose fog of war is drawn
+    pub const PLAYER: Team = Team(0);
+    // The computer controlled opponent
+    pub const ENEMY: Team = Team(1);
+
+    // Sprites of other teams are tinted so they can be told apart
+    pub fn color(&self) -> Color {
+        if *self == Team::PLAYER {
+            Color::WHITE
+        } else {
+            Color::rgb(1.0, 0.55, 0.55)
+        }
+    }
+}
+
 #[derive(Component, Debug)]
 pub struct Body {
     pub entity: Entity,
@@ -185,39 +414,27 @@ fn animate_wings(
     }
 }
 
-fn place_bee(
+// Gives every newly simulated bee its body sprite and animated wings
+fn attach_bee_sprites(
     mut commands: Commands,
-    mouse_position: Res<MousePosition>,
     textures: Res<TextureAssets>,
-    mouse_input: Res<Input<MouseButton>>,
+    q_bees: Query<(Entity, &Transform, &Team, &BeeVariant), Added<Bee>>,
 ) {
-    let mut rng = rand::thread_rng();
-    let bee_body= if rng.gen::<bool>() {
-        textures.beebody1.clone()
-    } else {
-        textures.beebody2.clone()
-    };
-    if mouse_input.just_pressed(MouseButton::Right) {
-        let bee_entity = commands.spawn((
-            SpriteBundle {
-                texture: bee_body,
-                transform: Transform::from_xyz(
-                    mouse_position.0.x,
-                    mouse_position.0.y,
-                    5.0,
-                ),
-                ..default()
-            },
-            Bee,
-            BeeBehavior::Destination(Vec2::new(mouse_position.0.x, mouse_position.0.y)),
-            Boid,
-            Highlightable,
-            Collider::new(5.0),
-            Velocity::default(),
-        )).id();
+    for (bee_entity, transform, team, variant) in q_bees.iter() {
+        let bee_body = if variant.body == 0 {
+            textures.beebody1.clone()
+        } else {
+            textures.beebody2.clone()
+        };
+        commands.entity(bee_entity).insert(SpriteBundle {
+            sprite: Sprite { color: team.color(), ..default() },
+            texture: bee_body,
+            transform: transform.with_scale(Vec3::splat(variant.scale)),
+            ..default()
+        });
 
         // Spawn the wings as a child of the bee body
-        let bee_wings= if rng.gen::<bool>() {
+        let bee_wings = if variant.wings == 0 {
             textures.bee1wingmap.clone()
         } else {
             textures.bee2wingmap.clone()
@@ -225,37 +442,12 @@ fn place_bee(
         commands.entity(bee_entity).with_children(|parent| {
             parent.spawn(SpriteSheetBundle {
                 texture_atlas: bee_wings,
-                sprite: TextureAtlasSprite::new(0), // Set the initial sprite index
+                sprite: TextureAtlasSprite { color: team.color(), ..TextureAtlasSprite::new(0) }, // Set the initial sprite index
                 transform: Transform::from_xyz(0.0, 0.0, 1.0), // Adjust the position of the wings relative to the bee body
                 ..Default::default()
             })
                 .insert(AnimationIndices { first: 0, last: 3 - 1 })
                 .insert(AnimationTimer(Timer::from_seconds(0.3, TimerMode::Repeating)));
         });
-
-        info!("Bee spawned");
     }
 }
-
-fn _clear_fog(
-    mut commands: Commands,
-    bee_query: Query<&Transform, With<Bee>>,
-    mut fog_tile_query: Query<(Entity, &Transform), With<FogTile>>,
-) {
-    for bee_transform in bee_query.iter() {
-        // info!("Bee transform {:?}", bee_transform);
-        for (fog_tile_entity, fog_transform) in fog_tile_query.iter_mut() {
-            // info!("Fogtile transform {:?}", fog_transform);
-            let distance = bee_transform.translation.distance(fog_transform.translation);
-            info!("Distance {:?}", distance);
-            // Define a range where the fog tile should despawn (adjust this range as needed)
-            let despawn_range = 30000.0; // Modify this value to suit your needs
-
-            if distance < despawn_range {
-                // Despawn fog tile if it's within the despawn range of the bee
-                info!("Collision detected: Distance between bee and fog tile: {}", distance);
-                commands.entity(fog_tile_entity).despawn_recursive();
-            }
-        }
-    }
-}
\ No newline at end of file
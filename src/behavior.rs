@@ -0,0 +1,328 @@
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::bees::{BeeArrived, BoidGroup, Steering, Team, Velocity};
+use crate::boids::Position;
+use crate::combat::Health;
+use crate::economy::Cargo;
+use crate::simulation::GameRng;
+use crate::hive::Hive;
+use crate::navigation::{FlightPath, FlowFields, NavGrid};
+use crate::queen::Queen;
+use crate::tilemap::Flower;
+
+// What a bee is currently doing, driven every tick by `run_behaviors`
+//
+// Traveling / Marching --arrive--> Idle
+// Foraging --cargo full or flower empty--> Returning --unloaded at hive--> Foraging (same flower) / Idle
+// Escorting follows the queen until she is gone, then Idle
+// Attacking chases its target until it is dead or gone, then Idle
+// Exploring runs until the bee is given something else to do
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub enum BeeBehavior {
+    Traveling(Vec2), // Destination coordinates
+    Marching(Vec2), // Traveling to its own slot of a formation
+    Exploring { origin: Vec2, wander: f32 }, // Point of origin coordinates, current wander angle
+    Foraging { flower: Entity }, // Flower to collect from
+    Returning { hive: Entity, flower: Option<Entity> }, // Hive to bring the haul to, flower to go back to
+    Idle(Vec2), // Hanging around the point it arrived at
+    Escorting(Entity), // Queen to stay close to
+    Attacking(Entity), // Enemy to chase and sting
+}
+
+// Just the state of a `BeeBehavior`, without the data it carries
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BeeState {
+    Traveling,
+    Marching,
+    Exploring,
+    Foraging,
+    Returning,
+    Idle,
+    Escorting,
+    Attacking,
+}
+
+impl BeeBehavior {
+    pub fn state(&self) -> BeeState {
+        match self {
+            BeeBehavior::Traveling(_) => BeeState::Traveling,
+            BeeBehavior::Marching(_) => BeeState::Marching,
+            BeeBehavior::Exploring { .. } => BeeState::Exploring,
+            BeeBehavior::Foraging { .. } => BeeState::Foraging,
+            BeeBehavior::Returning { .. } => BeeState::Returning,
+            BeeBehavior::Idle(_) => BeeState::Idle,
+            BeeBehavior::Escorting(_) => BeeState::Escorting,
+            BeeBehavior::Attacking(_) => BeeState::Attacking,
+        }
+    }
+
+    // Whatever the bee is flying to, so obstacle avoidance lets it get there
+    pub fn goal(&self) -> (Option<Vec2>, Option<Entity>) {
+        match *self {
+            BeeBehavior::Traveling(point) | BeeBehavior::Marching(point) | BeeBehavior::Idle(point) => (Some(point), None),
+            BeeBehavior::Foraging { flower } => (None, Some(flower)),
+            BeeBehavior::Returning { hive, .. } => (None, Some(hive)),
+            BeeBehavior::Escorting(target) | BeeBehavior::Attacking(target) => (None, Some(target)),
+            BeeBehavior::Exploring { .. } => (None, None),
+        }
+    }
+}
+
+// The state a bee was in when `track_state_changes` last looked at it
+#[derive(Component, Clone, Copy, Debug)]
+pub struct LastBeeState(pub BeeState);
+
+// Sent whenever a bee switches state, no matter which system switched it, so UI and audio can react
+#[derive(Event, Debug, Clone, Copy)]
+pub struct BeeStateChanged {
+    pub entity: Entity,
+    pub from: BeeState,
+    pub to: BeeState,
+}
+
+// How strongly each steering rule acts on a bee in a given state
+#[derive(Clone, Copy, Debug, Reflect)]
+pub struct SteeringWeights {
+    pub flocking: f32, // Multiplies cohesion and alignment of the group
+    pub separation: f32, // Multiplies separation of the group
+    pub seek: f32,
+    pub wander: f32,
+    pub flow: f32, // Following the flow field of the destination around walls, on top of seeking it
+}
+
+#[derive(Resource, Clone, Debug, Reflect)]
+#[reflect(Resource)]
+pub struct BehaviorWeights {
+    pub traveling: SteeringWeights,
+    pub marching: SteeringWeights,
+    pub exploring: SteeringWeights,
+    pub foraging: SteeringWeights,
+    pub returning: SteeringWeights,
+    pub idle: SteeringWeights,
+    pub escorting: SteeringWeights,
+    pub attacking: SteeringWeights,
+}
+
+impl Default for BehaviorWeights {
+    fn default() -> Self {
+        BehaviorWeights {
+            traveling: SteeringWeights { flocking: 1.0, separation: 1.0, seek: 1.0, wander: 0.0, flow: 2.0 },
+            // In formation every bee flies for its own slot, only keeping clear of the others
+            marching: SteeringWeights { flocking: 0.0, separation: 1.0, seek: 1.0, wander: 0.0, flow: 2.0 },
+            exploring: SteeringWeights { flocking: 0.5, separation: 0.5, seek: 0.0, wander: 1.0, flow: 0.0 },
+            // Bees on a job care more about getting there than sticking with the swarm
+            foraging: SteeringWeights { flocking: 0.3, separation: 0.3, seek: 1.2, wander: 0.0, flow: 0.0 },
+            returning: SteeringWeights { flocking: 0.3, separation: 0.3, seek: 1.2, wander: 0.0, flow: 0.0 },
+            idle: SteeringWeights { flocking: 1.0, separation: 1.0, seek: 0.5, wander: 0.0, flow: 0.0 },
+            escorting: SteeringWeights { flocking: 1.0, separation: 1.0, seek: 1.0, wander: 0.0, flow: 0.0 },
+            attacking: SteeringWeights { flocking: 0.3, separation: 0.3, seek: 1.5, wander: 0.0, flow: 0.0 },
+        }
+    }
+}
+
+impl BehaviorWeights {
+    pub fn get(&self, state: BeeState) -> SteeringWeights {
+        match state {
+            BeeState::Traveling => self.traveling,
+            BeeState::Marching => self.marching,
+            BeeState::Exploring => self.exploring,
+            BeeState::Foraging => self.foraging,
+            BeeState::Returning => self.returning,
+            BeeState::Idle => self.idle,
+            BeeState::Escorting => self.escorting,
+            BeeState::Attacking => self.attacking,
+        }
+    }
+}
+
+// Arrive behaviour: full speed towards `target`, slowing inside the group's slowing radius
+// Returns the force, the throttle and whether the bee is within the arrive radius
+pub fn arrive(position: Vec3, heading: Vec3, target: Vec2, group: &BoidGroup) -> (Vec3, f32, bool) {
+    let offset = target.extend(position.z) - position;
+    let distance = offset.length();
+    if distance <= group.arrive_radius {
+        return (Vec3::ZERO, 0.2, true);
+    }
+    let throttle = (distance / group.slowing_radius).clamp(0.2, 1.0);
+    ((offset / distance - heading) * group.seek, throttle, false)
+}
+
+// `arrive` that goes around walls, through the waypoints `plan_paths` found for the bee's `FlightPath`
+// Only slows down on the last leg
+fn arrive_around(path: &mut FlightPath, position: Vec3, heading: Vec3, target: Vec2, group: &BoidGroup) -> (Vec3, f32, bool) {
+    match path.towards(target) {
+        Some(waypoint) => (arrive(position, heading, waypoint, group).0, 1.0, false),
+        None => arrive(position, heading, target, group),
+    }
+}
+
+// How far from their queen escorting bees may drift before flying after her
+pub const ESCORT_RADIUS: f32 = 400.0;
+
+// Steers every bee according to its `BeeBehavior` and moves it along to the next state
+pub fn run_behaviors(
+    mut bees: Query<(Entity, &Position, &Velocity, &Team, &Cargo, &mut BeeBehavior, &mut Steering, &mut FlightPath)>,
+    groups: Query<&BoidGroup>,
+    flowers: Query<(&Transform, &Flower)>,
+    hives: Query<(Entity, &Transform, &Team), With<Hive>>,
+    queens: Query<&Position, With<Queen>>,
+    targets: Query<(Option<&Position>, &Transform), With<Health>>,
+    weights: Res<BehaviorWeights>,
+    nav_grid: Option<Res<NavGrid>>,
+    mut flow_fields: ResMut<FlowFields>,
+    mut rng: ResMut<GameRng>,
+    mut arrivals: EventWriter<BeeArrived>,
+) {
+    for (entity, position, velocity, team, cargo, mut behavior, mut steering, mut path) in bees.iter_mut() {
+        let Some(group) = groups.iter().find(|group| group.id == team.0) else {
+            continue;
+        };
+        let heading = velocity.0.normalize_or_zero();
+        let weight = weights.get(behavior.state());
+        steering.flocking = weight.flocking;
+        steering.separation = weight.separation;
+
+        match *behavior {
+            BeeBehavior::Traveling(target) | BeeBehavior::Marching(target) => {
+                // Ordered bees share the flow field of their destination instead of each planning a path
+                let (force, throttle, arrived) = arrive(position.0, heading, target, group);
+                if arrived {
+                    *behavior = BeeBehavior::Idle(target);
+                    arrivals.send(BeeArrived { entity, position: target });
+                    continue;
+                }
+                steering.force += force * weight.seek;
+                steering.throttle = steering.throttle.min(throttle);
+                // Around walls the flow field pulls harder than the straight line to the destination
+                let flow = nav_grid
+                    .as_deref()
+                    .and_then(|grid| flow_fields.direction(grid, position.0.truncate(), target));
+                if let Some(direction) = flow {
+                    steering.force += (direction.extend(0.0) - heading) * group.seek * weight.flow;
+                }
+            }
+            BeeBehavior::Exploring { origin, wander } => {
+                // Wander: keep heading in a slowly drifting random direction
+                let wander = wander + rng.gen_range(-0.3..0.3);
+                let desired = Vec2::from_angle(wander).extend(0.0);
+                steering.force += (desired - heading) * weight.wander;
+                *behavior = BeeBehavior::Exploring { origin, wander };
+            }
+            BeeBehavior::Foraging { flower } => {
+                let Ok((flower_transform, flower_data)) = flowers.get(flower) else {
+                    // Someone got to the flower first
+                    *behavior = BeeBehavior::Idle(position.0.truncate());
+                    continue;
+                };
+                // Castes that can't carry anything wait at the flower instead
+                if cargo.capacity <= 0.0 {
+                    *behavior = BeeBehavior::Idle(flower_transform.translation.truncate());
+                    continue;
+                }
+                let flower_position = flower_transform.translation.truncate();
+                let (force, throttle, arrived) = arrive_around(&mut path, position.0, heading, flower_position, group);
+                steering.force += force * weight.seek;
+                steering.throttle = steering.throttle.min(throttle);
+                // Collecting happens in `harvest_flowers`, fly home once there is no more room or no more pollen
+                let picked_clean = flower_data.pollen <= 0.0 && !cargo.is_empty();
+                if !arrived || !(cargo.is_full() || picked_clean) {
+                    continue;
+                }
+                *behavior = match nearest_hive(position.0, team, &hives) {
+                    Some(hive) => BeeBehavior::Returning { hive, flower: Some(flower) },
+                    None => BeeBehavior::Idle(position.0.truncate()),
+                };
+            }
+            BeeBehavior::Returning { hive, flower } => {
+                let Ok((_, hive_transform, _)) = hives.get(hive) else {
+                    *behavior = match nearest_hive(position.0, team, &hives) {
+                        Some(hive) => BeeBehavior::Returning { hive, flower },
+                        None => BeeBehavior::Idle(position.0.truncate()),
+                    };
+                    continue;
+                };
+                let hive_position = hive_transform.translation.truncate();
+                let (force, throttle, arrived) = arrive_around(&mut path, position.0, heading, hive_position, group);
+                steering.force += force * weight.seek;
+                steering.throttle = steering.throttle.min(throttle);
+                // Unloading happens in `deposit_at_hives`
+                if !arrived || !cargo.is_empty() {
+                    continue;
+                }
+                // Haul delivered, head back out for more if the flower is still around
+                *behavior = match flower.filter(|flower| flowers.contains(*flower)) {
+                    Some(flower) => BeeBehavior::Foraging { flower },
+                    None => BeeBehavior::Idle(hive_position),
+                };
+            }
+            BeeBehavior::Idle(anchor) => {
+                // Drift around slowly, only pulled back when wandering too far off
+                let offset = anchor.extend(position.0.z) - position.0;
+                if offset.length() > group.slowing_radius {
+                    steering.force += (offset.normalize() - heading) * group.seek * weight.seek;
+                }
+                steering.throttle = steering.throttle.min(0.3);
+            }
+            BeeBehavior::Escorting(queen) => {
+                let Ok(queen_position) = queens.get(queen) else {
+                    *behavior = BeeBehavior::Idle(position.0.truncate());
+                    continue;
+                };
+                // Catch up when she moves off, otherwise circle around her like an idle bee
+                let offset = queen_position.0 - position.0;
+                if offset.length() > ESCORT_RADIUS {
+                    let (force, throttle, _) =
+                        arrive_around(&mut path, position.0, heading, queen_position.0.truncate(), group);
+                    steering.force += force * weight.seek;
+                    steering.throttle = steering.throttle.min(throttle);
+                } else {
+                    steering.throttle = steering.throttle.min(0.3);
+                }
+            }
+            BeeBehavior::Attacking(target) => {
+                let Ok((target_position, transform)) = targets.get(target) else {
+                    // Dead, or never there
+                    *behavior = BeeBehavior::Idle(position.0.truncate());
+                    continue;
+                };
+                // Charge at full speed, the sting happens on contact in `resolve_stings`
+                let target_position = target_position.map_or(transform.translation, |target| target.0).truncate();
+                let aim = path.towards(target_position).unwrap_or(target_position);
+                if let Some(desired) = (aim - position.0.truncate()).extend(0.0).try_normalize() {
+                    steering.force += (desired - heading) * group.seek * weight.seek;
+                }
+            }
+        }
+    }
+}
+
+fn nearest_hive(
+    position: Vec3,
+    team: &Team,
+    hives: &Query<(Entity, &Transform, &Team), With<Hive>>,
+) -> Option<Entity> {
+    hives
+        .iter()
+        .filter(|(_, _, hive_team)| *hive_team == team)
+        .min_by(|(_, a, _), (_, b, _)| {
+            a.translation
+                .distance_squared(position)
+                .total_cmp(&b.translation.distance_squared(position))
+        })
+        .map(|(hive, _, _)| hive)
+}
+
+// Sends a `BeeStateChanged` for every bee whose state differs from the last time we looked
+pub fn track_state_changes(
+    mut bees: Query<(Entity, &BeeBehavior, &mut LastBeeState), Changed<BeeBehavior>>,
+    mut changes: EventWriter<BeeStateChanged>,
+) {
+    for (entity, behavior, mut last) in bees.iter_mut() {
+        let state = behavior.state();
+        if state != last.0 {
+            changes.send(BeeStateChanged { entity, from: last.0, to: state });
+            last.0 = state;
+        }
+    }
+}
@@ -3,147 +3,339 @@ use crate::{
     // world::LdtkLevel,
 };
 use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
 // use bevy_ecs_ldtk::prelude::*;
 
-use crate::bees::{BoidGroup, Collider, Velocity};
-use crate::tilemap::LevelData;
+use crate::bees::{BeeBehavior, BoidGroup, Collider, Steering, Velocity};
+use crate::caste::{Caste, Castes};
+use crate::obstacles::{obstacle_steering, ObstacleIndex};
+use crate::level::LevelData;
 
 #[derive(Component)]
 pub struct Boid;
 
-pub fn create_boid_group(
+// Where the simulation says an entity is, as of the last fixed tick
+// The `Transform` is only the rendered position, interpolated between `PreviousPosition` and this
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Position(pub Vec3);
+
+// Position at the start of the last fixed tick, used for interpolating the rendered transform
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PreviousPosition(pub Vec3);
+
+// Spawns a `BoidGroup` for every team that has boids but no group yet
+pub fn create_boid_groups(
     mut comms: Commands,
     // level: Res<Assets<LdtkProject>>,
     // handle: Res<LdtkLevel>,
     level_data: Res<LevelData>,
-    mut loaded: Local<bool>,
+    boids: Query<&Team, With<Boid>>,
+    mut groups: Query<&mut BoidGroup>,
 ) {
-    if *loaded {
-        return;
+    // The world border of every group follows the level
+    if level_data.is_changed() {
+        for mut group in groups.iter_mut() {
+            group.set_bounds(Vec2::ZERO, Vec2::new(level_data.level_width, level_data.level_height));
+        }
+    }
+    let mut known: HashSet<u32> = groups.iter().map(|group| group.id).collect();
+    for team in boids.iter() {
+        if !known.insert(team.0) {
+            continue;
+        }
+        let height = level_data.level_height;
+        let width = level_data.level_width;
+        info!("Creating boid group for team {}, maximum width {}, height {}", team.0, width, height);
+        comms.spawn(BoidGroup::new(
+            Vec2::new(0., 0.),
+            Vec2::new(width, height),
+            *team,
+        ));
     }
-    let height = level_data.level_height;
-    let width = level_data.level_width;
-    info!("Maximum width {}, height {}", width, height);
-    comms.spawn(BoidGroup::new(
-        Vec2::new(0., 0.),
-        Vec2::new(width, height),
-        Team(0),
-    ));
-    *loaded = true;
 }
 
 pub fn update_boids(
-    mut query: Query<(&Transform, &mut Collider, &mut Velocity)>,
+    mut query: Query<(
+        &Position,
+        &Team,
+        &mut Collider,
+        &mut Velocity,
+        Option<&mut Steering>,
+        Option<&Caste>,
+        Option<&BeeBehavior>,
+    )>,
     universe: Query<&BoidGroup>,
+    castes: Res<Castes>,
+    obstacles: Option<Res<ObstacleIndex>>,
 ) {
-    // TODO: dont let this crash, add this only once
-    let universe = match universe.get_single() {
-        Ok(a) => a,
-        Err(_) => return,
-    };
+    let groups: HashMap<u32, &BoidGroup> = universe.iter().map(|group| (group.id, group)).collect();
     query
         .iter_mut()
-        .for_each(|(transform, collider, mut velocity)| {
-            let x = transform.translation.x as i32;
-            let y = transform.translation.y as i32;
-            // let win = universe.graph.size();
-
+        .for_each(|(position, team, collider, mut velocity, steering_rules, caste, behavior)| {
+            // Boids only flock once their team has a group
+            let Some(universe) = groups.get(&team.0) else {
+                return;
+            };
+            let vision = universe.vision * caste.map_or(1.0, |caste| castes.get(*caste).vision);
             // -------------------- collision query --------------------
             let query_region = collider
-                .into_region(transform.translation)
-                .with_margin((universe.vision) as i32);
+                .into_region(position.0)
+                .with_margin(vision as i32);
             let exclude = match &collider.id {
                 Some(id) => vec![id.clone()],
                 None => vec![],
             };
 
-            let collisions = universe.graph.query(&query_region, &exclude);
+            // The square query region is only a broad phase, neighbors are the ones inside the vision cone
+            let neighbors: Vec<(Vec3, Vec3)> = universe
+                .graph
+                .query(&query_region, &exclude)
+                .into_iter()
+                .filter(|body| universe.can_see(position.0, velocity.0, body.position, vision))
+                .map(|body| (body.position, body.velocity))
+                .collect();
+            let heading = velocity.0.normalize_or_zero();
+            // seeking and everything else that ran in `BoidSet::Steer`, used up once applied
+            let rules = match steering_rules {
+                Some(mut rules) => std::mem::take(&mut *rules),
+                None => Steering::default(),
+            };
 
-            let (mass_center, aligment, separation) = collisions.iter().fold(
-                (Vec3::ZERO, Vec3::ZERO, Vec3::ZERO),
-                |(mcen, alg, sep), body| {
-                    (
-                        mcen + body.position.normalize(),
-                        alg + body.velocity.normalize(),
-                        sep + (transform.translation - body.position).normalize(),
-                    )
-                },
-            );
+            let (flocking, separation) = flocking_steering(position.0, heading, &neighbors, universe);
+            let mut steering = flocking * rules.flocking + separation * rules.separation;
 
-            let mut direction = velocity.0.normalize();
+            // -------------------- Avoidance --------------------
+            // Every other team is only something to keep away from
+            let no_exclude = vec![];
+            let others: Vec<Vec3> = groups
+                .values()
+                .filter(|other| other.id != team.0)
+                .flat_map(|other| other.graph.query(&query_region, &no_exclude))
+                .map(|body| body.position)
+                .collect();
+            steering += separation_steering(position.0, others, vision) * universe.avoidance;
 
-            // -------------------- Cohesion --------------------
-            if mass_center.length() > 0.0 {
-                direction += (mass_center.normalize() - transform.translation.normalize())
-                    .normalize()
-                    * universe.cohesion;
+            // -------------------- Obstacles --------------------
+            // Walls, hives and big flowers, looking ahead so the boid turns before it gets there
+            // Whatever it is flying to is not in the way
+            if let Some(obstacles) = obstacles.as_deref() {
+                let (goal_point, goal_entity) = behavior.map_or((None, None), |behavior| behavior.goal());
+                let (origin, direction) = (position.0.truncate(), heading.truncate());
+                let half_size = collider.radius / 2.0;
+                let in_the_way = obstacles
+                    .ahead(origin, direction, universe.look_ahead, half_size)
+                    .into_iter()
+                    .filter(|obstacle| obstacle.entity.is_none() || obstacle.entity != goal_entity)
+                    .filter(|obstacle| {
+                        goal_point.map_or(true, |goal| (goal - obstacle.center).abs().cmpgt(obstacle.half_size).any())
+                    });
+                let avoid = obstacle_steering(origin, direction, universe.look_ahead, half_size, in_the_way);
+                steering += avoid.extend(0.0) * universe.obstacle_avoidance;
             }
 
-            // -------------------- Alignment --------------------
-            if aligment.length() > 0.0 {
-                direction += aligment.normalize() * universe.alignment;
-            }
+            // -------------------- World Border --------------------
+            steering += universe.border_steering(position.0);
 
-            // -------------------- Separation --------------------
-            if separation.length() > 0.0 {
-                direction += separation.normalize() * universe.separation;
-            }
+            // -------------------- Other rules --------------------
+            steering += rules.force;
 
-            let mut new_velocity = direction.normalize() * velocity.0.length();
-
-            // -------------------- World Border --------------------
-            // this barely works, but it does work
-            let margin: i32 = 20;
-            if (x < 0 + margin && velocity.0.x < 0.0) || (x > 3840 - margin && velocity.0.x > 0.0) {
-                new_velocity.x *= -1.0;
-            }
-            if (y < 0 + margin && velocity.0.y < 0.0) || (y > 2160 - margin && velocity.0.y > 0.0) {
-                new_velocity.y *= -1.0;
-            }
+            // A boid can only turn so fast
+            let steering = steering.clamp_length_max(universe.max_force);
+            let new_velocity = (heading + steering).truncate().extend(0.0);
+            let new_velocity = new_velocity.try_normalize().unwrap_or(heading) * rules.throttle;
 
             // finally set the new velocity
             velocity.0 = new_velocity;
         });
 }
 
+// Reynolds' flocking rules for a single boid, as steering forces on its unit `heading`
+// `neighbors` are the (position, velocity) of every boid it can see
+// Returns cohesion plus alignment, and separation on its own so formations can keep only that
+pub fn flocking_steering(
+    position: Vec3,
+    heading: Vec3,
+    neighbors: &[(Vec3, Vec3)],
+    group: &BoidGroup,
+) -> (Vec3, Vec3) {
+    if neighbors.is_empty() {
+        return (Vec3::ZERO, Vec3::ZERO);
+    }
+    let count = neighbors.len() as f32;
+
+    // -------------------- Cohesion --------------------
+    // steer towards the center of mass of the neighbors
+    let mass_center = neighbors.iter().map(|(p, _)| *p).sum::<Vec3>() / count;
+    let cohesion = match (mass_center - position).try_normalize() {
+        Some(desired) => desired - heading,
+        None => Vec3::ZERO,
+    };
+
+    // -------------------- Alignment --------------------
+    // steer towards the average heading of the neighbors
+    let average_velocity = neighbors.iter().map(|(_, v)| *v).sum::<Vec3>() / count;
+    let alignment = match average_velocity.try_normalize() {
+        Some(desired) => desired - heading,
+        None => Vec3::ZERO,
+    };
+
+    // -------------------- Separation --------------------
+    let separation = separation_steering(
+        position,
+        neighbors.iter().map(|(p, _)| *p),
+        group.separation_radius,
+    );
+
+    (cohesion * group.cohesion + alignment * group.alignment, separation * group.separation)
+}
+
+// Push away from everything closer than `radius`, harder the closer it is
+// Averaged over the pushing neighbors, a neighbor right at `radius` pushes with strength 1
+pub fn separation_steering(position: Vec3, others: impl IntoIterator<Item = Vec3>, radius: f32) -> Vec3 {
+    let (push, count) = others
+        .into_iter()
+        .fold((Vec3::ZERO, 0), |(push, count), other| {
+            let offset = position - other;
+            let distance = offset.length();
+            // Exactly on top of each other gives no direction to push in
+            if distance >= radius || distance <= f32::EPSILON {
+                return (push, count);
+            }
+            (push + offset / distance * (radius / distance), count + 1)
+        });
+    if count == 0 {
+        Vec3::ZERO
+    } else {
+        push / count as f32
+    }
+}
+
 pub fn move_system(
-    mut query: Query<(&mut Transform, &Velocity)>,
+    mut query: Query<(&mut Position, Option<&mut PreviousPosition>, &Team, &mut Velocity, Option<&Caste>)>,
     universe: Query<&BoidGroup>,
-    time: Res<Time>,
+    castes: Res<Castes>,
+    // Always a whole tick, so movement does not depend on the frame rate
+    time: Res<Time<Fixed>>,
 ) {
-    let universe = match universe.get_single() {
-        Ok(a) => a,
-        Err(_) => return,
-    };
-    query.par_iter_mut().for_each(|(mut transform, velocity)| {
-        let direction = velocity.0.normalize();
-        let rotation = Quat::from_rotation_z(-direction.x.atan2(direction.y));
-        transform.rotation = rotation;
+    let groups: HashMap<u32, &BoidGroup> = universe.iter().map(|group| (group.id, group)).collect();
+    query.par_iter_mut().for_each(|(mut position, previous, team, mut velocity, caste)| {
+        let Some(universe) = groups.get(&team.0) else {
+            return;
+        };
+        let speed = universe.speed * caste.map_or(1.0, |caste| castes.get(*caste).speed);
+        position.0 += velocity.0 * time.delta_seconds() * speed;
+
+        // -------------------- World Border --------------------
+        let teleported = universe.confine(&mut position.0, &mut velocity.0);
+        // Don't interpolate across the whole map when wrapping around
+        if let Some(mut previous) = previous {
+            previous.0 += teleported;
+        }
+        position.0.z = 5.;
+    });
+}
+
+// Remember where everything was before this tick moves it
+pub fn store_previous_positions(mut query: Query<(&Position, &mut PreviousPosition)>) {
+    query.par_iter_mut().for_each(|(position, mut previous)| {
+        previous.0 = position.0;
+    });
+}
 
-        transform.translation += velocity.0 * time.delta_seconds() * universe.speed;
-        transform.translation.z = 5.;
+// Blend the rendered transforms between the last two ticks by how far we are into the next one
+pub fn interpolate_transforms(
+    time: Res<Time<Fixed>>,
+    mut query: Query<(&mut Transform, &Position, &PreviousPosition, Option<&Velocity>)>,
+) {
+    let alpha = time.overstep_percentage();
+    query.par_iter_mut().for_each(|(mut transform, position, previous, velocity)| {
+        transform.translation = previous.0.lerp(position.0, alpha);
+        if let Some(direction) = velocity.and_then(|v| v.0.try_normalize()) {
+            transform.rotation = Quat::from_rotation_z(-direction.x.atan2(direction.y));
+        }
     });
 }
 
+// Every group gets a fresh quadtree holding only the boids of its own team
 pub fn build_or_update_quadtree(
-    mut query: Query<(Entity, &Transform, &mut Collider, &Velocity), With<Boid>>,
+    mut query: Query<(Entity, &Position, &Team, &mut Collider, &Velocity), With<Boid>>,
     mut universe: Query<&mut BoidGroup>,
 ) {
-    let mut universe = match universe.get_single_mut() {
-        Ok(a) => a,
-        Err(_) => return,
-    };
-    universe.graph.clear();
+    let mut groups: HashMap<u32, Mut<BoidGroup>> = universe
+        .iter_mut()
+        .map(|mut group| {
+            group.graph.clear();
+            group.count = 0;
+            (group.id, group)
+        })
+        .collect();
     query
         .iter_mut()
-        .for_each(|(entity, transform, mut collider, velocity)| {
+        .for_each(|(entity, position, team, mut collider, velocity)| {
+            let Some(universe) = groups.get_mut(&team.0) else {
+                collider.id = None;
+                return;
+            };
             collider.id = Some(universe.graph.insert(
-                collider.into_region(transform.translation),
+                collider.into_region(position.0),
                 Body {
                     entity,
-                    position: transform.translation,
+                    position: position.0,
                     velocity: velocity.0,
                 },
             ));
+            universe.count += 1;
         });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only cohesion and separation at full strength, so each can be checked on its own
+    fn group() -> BoidGroup {
+        let mut group = BoidGroup::new(Vec2::ZERO, Vec2::splat(1000.0), Team::PLAYER);
+        group.cohesion = 1.0;
+        group.alignment = 0.0;
+        group.separation = 1.0;
+        group.separation_radius = 100.0;
+        group
+    }
+
+    #[test]
+    fn cohesion_steers_towards_the_center_of_mass() {
+        let neighbors = [(Vec3::new(500.0, 500.0, 0.0), Vec3::ZERO), (Vec3::new(500.0, -500.0, 0.0), Vec3::ZERO)];
+        let (cohesion, separation) = flocking_steering(Vec3::ZERO, Vec3::Y, &neighbors, &group());
+        // Straight along x to the middle of both, not diagonally to the first one
+        assert!(cohesion.abs_diff_eq(Vec3::X - Vec3::Y, 1e-5), "{cohesion:?}");
+        assert_eq!(separation, Vec3::ZERO);
+    }
+
+    #[test]
+    fn separation_pushes_harder_the_closer() {
+        let far = separation_steering(Vec3::ZERO, [Vec3::new(50.0, 0.0, 0.0)], 100.0);
+        let near = separation_steering(Vec3::ZERO, [Vec3::new(25.0, 0.0, 0.0)], 100.0);
+        assert!(far.abs_diff_eq(Vec3::new(-2.0, 0.0, 0.0), 1e-5), "{far:?}");
+        assert!(near.abs_diff_eq(Vec3::new(-4.0, 0.0, 0.0), 1e-5), "{near:?}");
+    }
+
+    #[test]
+    fn separation_is_averaged_over_neighbors_within_radius() {
+        let others = [Vec3::new(-50.0, 0.0, 0.0), Vec3::new(0.0, -50.0, 0.0), Vec3::new(500.0, 0.0, 0.0)];
+        let push = separation_steering(Vec3::ZERO, others, 100.0);
+        assert!(push.abs_diff_eq(Vec3::new(1.0, 1.0, 0.0), 1e-5), "{push:?}");
+    }
+
+    #[test]
+    fn separation_ignores_coincident_and_distant_neighbors() {
+        let position = Vec3::new(10.0, 20.0, 0.0);
+        let push = separation_steering(position, [position, position + Vec3::new(100.0, 0.0, 0.0)], 100.0);
+        assert_eq!(push, Vec3::ZERO);
+    }
+
+    #[test]
+    fn no_neighbors_no_force() {
+        assert_eq!(flocking_steering(Vec3::ZERO, Vec3::X, &[], &group()), (Vec3::ZERO, Vec3::ZERO));
+        assert_eq!(separation_steering(Vec3::ZERO, [Vec3::ZERO; 0], 100.0), Vec3::ZERO);
+    }
+}
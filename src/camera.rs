@@ -1,7 +1,7 @@
 use bevy::input::mouse::MouseWheel;
 use bevy::prelude::*;
 use crate::GameState;
-use crate::tilemap::LevelData;
+use crate::level::LevelData;
 
 pub struct CameraPlugin;
 
@@ -11,7 +11,8 @@ impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
         app
             .add_systems(OnEnter(GameState::Playing), setup_camera_controls)
-            .add_systems(Update, panning_controls.run_if(in_state(GameState::Playing)))
+            // LDtk levels only have bounds to pan within once they are loaded
+            .add_systems(Update, panning_controls.run_if(in_state(GameState::Playing).and_then(resource_exists::<LevelData>())))
             .add_systems(Update, zooming_controls.run_if(in_state(GameState::Playing)));
     }
 }
@@ -30,6 +31,10 @@ fn panning_controls
     level_data: Res<LevelData>
 )
 {
+    // Ctrl is for the selection hotkeys, like Ctrl+A
+    if keys.pressed(KeyCode::ControlLeft) || keys.pressed(KeyCode::ControlRight) {
+        return;
+    }
     let pan_speed = 10.0;
     let mut cam_transform = q_cam.single_mut();
     // info!("Cam Translation {:?}", cam_transform.translation);
@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+
+use crate::bees::BeeVariant;
+
+// What a bee was raised to be, its stats come from the `Castes` table
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+pub enum Caste {
+    #[default]
+    Worker, // Forages, stings a little
+    Scout, // Fast and sees far, carries little
+    Soldier, // Tough and stings hard, does not forage
+    Drone, // Slow hauler with a big load, does not sting
+}
+
+impl Caste {
+    pub fn next(self) -> Self {
+        match self {
+            Caste::Worker => Caste::Scout,
+            Caste::Scout => Caste::Soldier,
+            Caste::Soldier => Caste::Drone,
+            Caste::Drone => Caste::Worker,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Reflect)]
+pub struct CasteStats {
+    pub speed: f32, // Multiplies the speed of the bee's group
+    pub vision: f32, // Multiplies the vision of the bee's group
+    pub collider: f32,
+    pub capacity: f32, // Pollen it can carry, nothing means it does not forage
+    pub health: f32,
+    pub damage: f32, // Per sting, nothing means it does not sting
+    pub reveal: f32,
+    pub cost: f32, // Honey it takes to raise one
+    #[reflect(ignore)]
+    pub sprite: BeeVariant,
+}
+
+// The stats of every caste, a resource so they can be tuned in the inspector
+#[derive(Resource, Clone, Debug, Reflect)]
+#[reflect(Resource)]
+pub struct Castes {
+    pub worker: CasteStats,
+    pub scout: CasteStats,
+    pub soldier: CasteStats,
+    pub drone: CasteStats,
+}
+
+impl Default for Castes {
+    fn default() -> Self {
+        Castes {
+            worker: CasteStats {
+                speed: 1.0,
+                vision: 1.0,
+                collider: 25.0,
+                capacity: 10.0,
+                health: 30.0,
+                damage: 3.0,
+                reveal: 900.0,
+                cost: 20.0,
+                sprite: BeeVariant { body: 0, wings: 0, scale: 1.0 },
+            },
+            scout: CasteStats {
+                speed: 1.4,
+                vision: 1.5,
+                collider: 20.0,
+                capacity: 2.0,
+                health: 15.0,
+                damage: 1.0,
+                reveal: 1800.0,
+                cost: 15.0,
+                sprite: BeeVariant { body: 1, wings: 1, scale: 0.8 },
+            },
+            soldier: CasteStats {
+                speed: 0.9,
+                vision: 1.0,
+                collider: 35.0,
+                capacity: 0.0,
+                health: 80.0,
+                damage: 10.0,
+                reveal: 700.0,
+                cost: 40.0,
+                sprite: BeeVariant { body: 1, wings: 0, scale: 1.3 },
+            },
+            drone: CasteStats {
+                speed: 0.8,
+                vision: 0.8,
+                collider: 30.0,
+                capacity: 25.0,
+                health: 50.0,
+                damage: 0.0,
+                reveal: 600.0,
+                cost: 30.0,
+                sprite: BeeVariant { body: 0, wings: 1, scale: 1.2 },
+            },
+        }
+    }
+}
+
+impl Castes {
+    pub fn get(&self, caste: Caste) -> CasteStats {
+        match caste {
+            Caste::Worker => self.worker,
+            Caste::Scout => self.scout,
+            Caste::Soldier => self.soldier,
+            Caste::Drone => self.drone,
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::bees::{BoidGroup, Collider, Team};
+use crate::boids::{Boid, Position};
+
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Health {
+    pub current: f32,
+    pub max: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        Health { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current <= 0.0
+    }
+}
+
+// Stings whatever of another team it touches
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Attack {
+    pub damage: f32,
+    pub cooldown: f32, // Seconds between two stings
+    pub ready_in: f32,
+}
+
+impl Attack {
+    pub fn new(damage: f32, cooldown: f32) -> Self {
+        Attack { damage, cooldown, ready_in: 0.0 }
+    }
+}
+
+// Sent for everything that died in a fight, right before it is despawned
+#[derive(Event, Debug, Clone, Copy)]
+pub struct Killed {
+    pub victim: Entity,
+    pub victim_team: Team,
+    pub killer: Entity,
+    pub killer_team: Team,
+    pub position: Vec2,
+}
+
+// Every attacker whose sting is ready hits one thing of another team its collider overlaps
+// Bees are found through the other groups' quadtrees, the few things that are not boids (queens, hives) are checked directly
+pub fn resolve_stings(
+    mut commands: Commands,
+    mut attackers: Query<(Entity, &Position, &Team, &Collider, &mut Attack)>,
+    mut victims: Query<(&Team, &mut Health, Option<&Position>, &Transform)>,
+    structures: Query<(Entity, &Team, &Collider, Option<&Position>, &Transform), (With<Health>, Without<Boid>)>,
+    groups: Query<&BoidGroup>,
+    time: Res<Time<Fixed>>,
+    mut kills: EventWriter<Killed>,
+) {
+    let no_exclude = vec![];
+    // Who hit a victim first this tick, so there is only one kill per death
+    let mut killed: HashMap<Entity, (Entity, Team)> = HashMap::new();
+    for (attacker, position, team, collider, mut attack) in attackers.iter_mut() {
+        attack.ready_in = (attack.ready_in - time.delta_seconds()).max(0.0);
+        if attack.ready_in > 0.0 || attack.damage <= 0.0 {
+            continue;
+        }
+        let region = collider.into_region(position.0);
+        let bee = groups
+            .iter()
+            .filter(|group| group.id != team.0)
+            .flat_map(|group| group.graph.query(&region, &no_exclude))
+            .map(|body| body.entity)
+            .find(|entity| !killed.contains_key(entity));
+        let target = bee.or_else(|| {
+            structures
+                .iter()
+                .filter(|(entity, other, _, _, _)| *other != team && !killed.contains_key(entity))
+                .find(|(_, _, other_collider, other_position, transform)| {
+                    let other_position = other_position.map_or(transform.translation, |position| position.0);
+                    // Colliders are squares `radius` wide, see `Collider::into_region`
+                    let reach = (collider.radius + other_collider.radius) / 2.0;
+                    let offset = (other_position - position.0).truncate().abs();
+                    offset.x <= reach && offset.y <= reach
+                })
+                .map(|(entity, ..)| entity)
+        });
+        let Some(target) = target else {
+            continue;
+        };
+        let Ok((_, mut health, _, _)) = victims.get_mut(target) else {
+            continue;
+        };
+        attack.ready_in = attack.cooldown;
+        health.current -= attack.damage;
+        if health.is_dead() {
+            killed.insert(target, (attacker, *team));
+        }
+    }
+    for (victim, (killer, killer_team)) in killed {
+        let Ok((victim_team, _, position, transform)) = victims.get(victim) else {
+            continue;
+        };
+        let position = position.map_or(transform.translation, |position| position.0).truncate();
+        info!("Team {} lost {:?} to team {}", victim_team.0, victim, killer_team.0);
+        kills.send(Killed { victim, victim_team: *victim_team, killer, killer_team, position });
+        // Takes the wing sprite and anything else attached along
+        commands.entity(victim).despawn_recursive();
+    }
+}
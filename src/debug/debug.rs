@@ -3,8 +3,9 @@ use bevy::prelude::*;
 
 use crate::{
     // bees::{Bee, BoidGroup, Collider, Velocity, BeeBehavior},
-    bees::BoidGroup,
+    bees::{BoidGroup, Team, Velocity},
     boids::Boid,
+    caste::{Caste, Castes},
     // loading::TextureAssets,
     GameState,
 };
@@ -23,6 +24,7 @@ impl Plugin for DebugPlugin {
                 (
                     visualize_quadtree,
                     visualize_boid_radius,
+                    visualize_boid_cone,
                     // spawn_random_boids,
                 )
                     .run_if(in_state(GameState::Playing)),
@@ -65,8 +67,9 @@ pub fn visualize_quadtree(mut gizmos: Gizmos, mut vis: ResMut<Visualizer>, group
 }
 
 pub fn visualize_boid_radius(
-    boids: Query<&Transform, With<Boid>>,
+    boids: Query<(&Transform, &Team, Option<&Caste>), With<Boid>>,
     groups: Query<&BoidGroup>,
+    castes: Res<Castes>,
     // mut comms: Commands,
     // mut mats: ResMut<Assets<ColorMaterial>>,
     // mut meshes: ResMut<Assets<Mesh>>,
@@ -77,11 +80,46 @@ pub fn visualize_boid_radius(
     if !vis.boid_vision {
         return;
     }
-    let Ok(group) = groups.get_single() else {
+    for (transform, team, caste) in &boids {
+        let Some(group) = groups.iter().find(|group| group.id == team.0) else {
+            continue;
+        };
+        // Same as `update_boids`, castes see further or less far than their group
+        let vision = group.vision * caste.map_or(1.0, |caste| castes.get(*caste).vision);
+        gizmos.circle_2d(transform.translation.xy(), vision, Color::PURPLE);
+    }
+}
+
+pub fn visualize_boid_cone(
+    boids: Query<(&Transform, &Velocity, &Team, Option<&Caste>), With<Boid>>,
+    groups: Query<&BoidGroup>,
+    castes: Res<Castes>,
+    mut gizmos: Gizmos,
+    mut vis: ResMut<Visualizer>,
+    input: Res<Input<KeyCode>>,
+) {
+    if input.just_pressed(KeyCode::C) {
+        vis.boid_cone = !vis.boid_cone;
+        info!("Toggling boid cone to {:?}", vis.boid_cone);
+    }
+    // draw the part of the vision circle that actually counts for flocking
+    if !vis.boid_cone {
         return;
-    };
-    for transform in &boids {
-        gizmos.circle_2d(transform.translation.xy(), group.vision, Color::PURPLE);
+    }
+    for (transform, velocity, team, caste) in &boids {
+        let Some(group) = groups.iter().find(|group| group.id == team.0) else {
+            continue;
+        };
+        let vision = group.vision * caste.map_or(1.0, |caste| castes.get(*caste).vision);
+        let Some(heading) = velocity.0.xy().try_normalize() else {
+            continue;
+        };
+        let position = transform.translation.xy();
+        let half_fov = group.fov.to_radians() / 2.0;
+        // arc_2d measures clockwise from Vec2::Y
+        gizmos.arc_2d(position, heading.x.atan2(heading.y), half_fov * 2.0, vision, Color::ORANGE);
+        gizmos.line_2d(position, position + Vec2::from_angle(half_fov).rotate(heading) * vision, Color::ORANGE);
+        gizmos.line_2d(position, position + Vec2::from_angle(-half_fov).rotate(heading) * vision, Color::ORANGE);
     }
 }
 
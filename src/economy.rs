@@ -0,0 +1,128 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+
+use crate::bees::{BeeBehavior, BoidGroup, Collider, Team};
+use crate::hive::Hive;
+use crate::tilemap::Flower;
+
+// Pollen a bee collects from a flower per second
+pub const HARVEST_RATE: f32 = 5.0;
+
+// Pollen a bee is carrying around
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Cargo {
+    pub amount: f32,
+    pub capacity: f32,
+}
+
+impl Cargo {
+    pub fn new(capacity: f32) -> Self {
+        Cargo { amount: 0.0, capacity }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.amount >= self.capacity
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.amount <= 0.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stockpile {
+    pub honey: f32,
+}
+
+// Honey stored across all hives of each team, keyed by team id
+// Tallied from the hives every tick, spend from a `Hive` rather than from here
+#[derive(Resource, Debug, Default)]
+pub struct Resources(pub HashMap<u32, Stockpile>);
+
+impl Resources {
+    pub fn get(&self, team: Team) -> Stockpile {
+        self.0.get(&team.0).copied().unwrap_or_default()
+    }
+
+    pub fn get_mut(&mut self, team: Team) -> &mut Stockpile {
+        self.0.entry(team.0).or_default()
+    }
+}
+
+pub fn regrow_flowers(mut flowers: Query<&mut Flower>, time: Res<Time<Fixed>>) {
+    for mut flower in flowers.iter_mut() {
+        if flower.pollen < flower.capacity {
+            flower.pollen = (flower.pollen + flower.regen * time.delta_seconds()).min(flower.capacity);
+        }
+    }
+}
+
+// Bees foraging a flower collect from it while touching it, found through the quadtrees of every group
+pub fn harvest_flowers(
+    mut flowers: Query<(Entity, &Transform, &Collider, &mut Flower)>,
+    mut bees: Query<(&BeeBehavior, &mut Cargo)>,
+    groups: Query<&BoidGroup>,
+    time: Res<Time<Fixed>>,
+) {
+    let no_exclude = vec![];
+    for (flower_entity, transform, collider, mut flower) in flowers.iter_mut() {
+        let region = collider.into_region(transform.translation);
+        for group in groups.iter() {
+            for body in group.graph.query(&region, &no_exclude) {
+                let Ok((behavior, mut cargo)) = bees.get_mut(body.entity) else {
+                    continue;
+                };
+                if *behavior != (BeeBehavior::Foraging { flower: flower_entity }) {
+                    continue;
+                }
+                let amount = (HARVEST_RATE * time.delta_seconds())
+                    .min(flower.pollen)
+                    .min(cargo.capacity - cargo.amount);
+                if amount <= 0.0 {
+                    continue;
+                }
+                flower.pollen -= amount;
+                cargo.amount += amount;
+            }
+        }
+    }
+}
+
+// Bees returning to a hive unload as soon as they touch it, the pollen is stored as honey
+pub fn deposit_at_hives(
+    mut hives: Query<(Entity, &Transform, &Collider, &Team, &mut Hive)>,
+    mut bees: Query<(&BeeBehavior, &mut Cargo)>,
+    groups: Query<&BoidGroup>,
+) {
+    let no_exclude = vec![];
+    for (hive_entity, transform, collider, team, mut hive) in hives.iter_mut() {
+        let Some(group) = groups.iter().find(|group| group.id == team.0) else {
+            continue;
+        };
+        let region = collider.into_region(transform.translation);
+        for body in group.graph.query(&region, &no_exclude) {
+            let Ok((behavior, mut cargo)) = bees.get_mut(body.entity) else {
+                continue;
+            };
+            if !matches!(*behavior, BeeBehavior::Returning { hive, .. } if hive == hive_entity) {
+                continue;
+            }
+            if cargo.is_empty() {
+                continue;
+            }
+            hive.honey += cargo.amount;
+            cargo.amount = 0.0;
+        }
+    }
+}
+
+pub fn tally_stockpiles(hives: Query<(&Team, &Hive)>, mut resources: ResMut<Resources>) {
+    let mut tally = Resources::default();
+    for (team, hive) in hives.iter() {
+        tally.get_mut(*team).honey += hive.honey;
+    }
+    // Only touch the resource when something changed, the HUD redraws on change
+    if tally.0 != resources.0 {
+        *resources = tally;
+    }
+}
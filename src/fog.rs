@@ -0,0 +1,241 @@
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use bevy_ecs_tilemap::prelude::*;
+
+use crate::bees::{BeeBehavior, BoidGroup, Steering, Team, Velocity};
+use crate::behavior::BeeState;
+use crate::boids::Position;
+use crate::level::LevelData;
+use crate::tilemap::Flower;
+
+// How far around itself an entity clears the fog, in world units
+#[derive(Component, Clone, Copy, Debug)]
+pub struct RevealRadius(pub f32);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FogState {
+    #[default]
+    Unexplored, // Never seen, fully covered
+    Explored, // Seen before: the terrain is known, but not what is on it now
+    Visible, // Seen right now
+}
+
+// Marks flowers and other teams' entities that the player can currently see
+// Anything without it is hidden, and player facing systems should not let it be targeted
+#[derive(Component, Debug)]
+pub struct Spotted;
+
+// Fog of war of every team, each with its own `FogGrid`
+#[derive(Resource, Debug)]
+pub struct FogOfWar {
+    pub level: LevelData,
+    grids: HashMap<u32, FogGrid>,
+}
+
+impl FogOfWar {
+    pub fn new(level: LevelData) -> Self {
+        FogOfWar { level, grids: HashMap::new() }
+    }
+
+    pub fn grid(&self, team: Team) -> Option<&FogGrid> {
+        self.grids.get(&team.0)
+    }
+
+    // Teams get their grid the first time they reveal something
+    pub fn grid_mut(&mut self, team: Team) -> &mut FogGrid {
+        let level = self.level;
+        self.grids.entry(team.0).or_insert_with(|| FogGrid::new(level))
+    }
+
+    pub fn state(&self, team: Team, world_pos: Vec2) -> FogState {
+        self.grid(team).map_or(FogState::Unexplored, |grid| grid.state_at(world_pos))
+    }
+
+    // Whether `team` can see what is at `world_pos` right now
+    pub fn is_visible(&self, team: Team, world_pos: Vec2) -> bool {
+        self.state(team, world_pos) == FogState::Visible
+    }
+}
+
+// Fog of war state of every tile of the level for a single team, keyed by `TilePos`
+#[derive(Debug)]
+pub struct FogGrid {
+    pub level: LevelData,
+    cells: Vec<FogState>,
+    visible: Vec<usize>, // Cells marked visible by the last update, so only those need resetting
+}
+
+impl FogGrid {
+    pub fn new(level: LevelData) -> Self {
+        FogGrid {
+            level,
+            cells: vec![FogState::default(); level.tile_count()],
+            visible: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, pos: &TilePos) -> Option<FogState> {
+        if !self.level.contains(pos) {
+            return None;
+        }
+        Some(self.cells[self.level.tile_index(pos)])
+    }
+
+    // Anything off the map counts as unexplored
+    pub fn state_at(&self, world_pos: Vec2) -> FogState {
+        self.level
+            .tile_pos(world_pos)
+            .and_then(|pos| self.get(&pos))
+            .unwrap_or_default()
+    }
+
+    // Closest unexplored tile to `from`, searching square rings outwards up to `max_radius` tiles away
+    pub fn nearest_unexplored(&self, from: TilePos, max_radius: u32) -> Option<TilePos> {
+        let from_center = self.level.world_pos(&from);
+        for radius in 0..=max_radius as i32 {
+            let mut best: Option<(TilePos, f32)> = None;
+            for dx in -radius..=radius {
+                for dy in -radius..=radius {
+                    // Only the ring, the inside was searched already
+                    if dx.abs() != radius && dy.abs() != radius {
+                        continue;
+                    }
+                    let (x, y) = (from.x as i32 + dx, from.y as i32 + dy);
+                    if x < 0 || y < 0 {
+                        continue;
+                    }
+                    let pos = TilePos { x: x as u32, y: y as u32 };
+                    if self.get(&pos) != Some(FogState::Unexplored) {
+                        continue;
+                    }
+                    let distance = self.level.world_pos(&pos).distance_squared(from_center);
+                    if best.map_or(true, |(_, best_distance)| distance < best_distance) {
+                        best = Some((pos, distance));
+                    }
+                }
+            }
+            // Ring distances overlap a bit, but the first ring with anything is close enough
+            if let Some((pos, _)) = best {
+                return Some(pos);
+            }
+        }
+        None
+    }
+
+    // Everything that was visible is only explored until revealed again
+    fn hide_all(&mut self) {
+        for index in self.visible.drain(..) {
+            self.cells[index] = FogState::Explored;
+        }
+    }
+
+    // Marks every tile whose center is within `radius` of `world_pos` as visible
+    // Only looks at the tiles in the bounding square of the circle
+    pub fn reveal(&mut self, world_pos: Vec2, radius: f32) {
+        let (size, tile_size) = (self.level.map_size, self.level.tile_size);
+        let center = (world_pos / tile_size).floor();
+        let reach = (radius / tile_size).ceil();
+        let min_x = (center.x - reach.x).max(0.0) as u32;
+        let min_y = (center.y - reach.y).max(0.0) as u32;
+        let max_x = (center.x + reach.x).min(size.x as f32 - 1.0);
+        let max_y = (center.y + reach.y).min(size.y as f32 - 1.0);
+        if max_x < 0.0 || max_y < 0.0 {
+            return;
+        }
+        for x in min_x..=max_x as u32 {
+            for y in min_y..=max_y as u32 {
+                let pos = TilePos { x, y };
+                if self.level.world_pos(&pos).distance_squared(world_pos) > radius * radius {
+                    continue;
+                }
+                let index = self.level.tile_index(&pos);
+                if self.cells[index] != FogState::Visible {
+                    self.cells[index] = FogState::Visible;
+                    self.visible.push(index);
+                }
+            }
+        }
+    }
+}
+
+// Where an exploring bee is currently headed: the closest unexplored tile it found
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Exploration {
+    pub target: Option<TilePos>,
+}
+
+// Pulls exploring bees towards the nearest unexplored tile of their team's fog, weighted by the group's `exploration`
+pub fn explore_fog(
+    mut bees: Query<(&Position, &Velocity, &Team, &BeeBehavior, &mut Exploration, &mut Steering)>,
+    groups: Query<&BoidGroup>,
+    fog: Res<FogOfWar>,
+) {
+    for (position, velocity, team, behavior, mut exploration, mut steering) in bees.iter_mut() {
+        if behavior.state() != BeeState::Exploring {
+            exploration.target = None;
+            continue;
+        }
+        let Some(group) = groups.iter().find(|group| group.id == team.0) else {
+            continue;
+        };
+        let Some(grid) = fog.grid(*team) else {
+            continue;
+        };
+        let Some(here) = grid.level.tile_pos(position.0.truncate()) else {
+            continue;
+        };
+        // Keep going for the same tile until somebody uncovers it
+        let still_unexplored = exploration
+            .target
+            .is_some_and(|target| grid.get(&target) == Some(FogState::Unexplored));
+        if !still_unexplored {
+            let max_radius = (group.exploration_range / grid.level.tile_size.max_element()).ceil() as u32;
+            exploration.target = grid.nearest_unexplored(here, max_radius);
+        }
+        let Some(target) = exploration.target else {
+            continue;
+        };
+        let offset = grid.level.world_pos(&target).extend(position.0.z) - position.0;
+        if let Some(desired) = offset.try_normalize() {
+            steering.force += (desired - velocity.0.normalize_or_zero()) * group.exploration;
+        }
+    }
+}
+
+// Recomputes which tiles every team sees from everything of theirs with a `RevealRadius`
+pub fn update_fog_grid(
+    mut fog: ResMut<FogOfWar>,
+    revealers: Query<(&RevealRadius, &Team, Option<&Position>, &Transform)>,
+) {
+    for grid in fog.grids.values_mut() {
+        grid.hide_all();
+    }
+    for (radius, team, position, transform) in revealers.iter() {
+        // Moving things are where the simulation says, static ones (the hive) where they were placed
+        let world_pos = position.map_or(transform.translation, |position| position.0);
+        fog.grid_mut(*team).reveal(world_pos.truncate(), radius.0);
+    }
+}
+
+// Flowers and other teams' entities are only `Spotted` while they stand in a tile the player sees
+pub fn update_spotted(
+    mut commands: Commands,
+    fog: Res<FogOfWar>,
+    entities: Query<
+        (Entity, Option<&Team>, Option<&Position>, &Transform, Has<Spotted>),
+        Or<(With<Flower>, With<Team>)>,
+    >,
+) {
+    for (entity, team, position, transform, spotted) in entities.iter() {
+        if team == Some(&Team::PLAYER) {
+            continue;
+        }
+        let world_pos = position.map_or(transform.translation, |position| position.0);
+        let visible = fog.is_visible(Team::PLAYER, world_pos.truncate());
+        if visible && !spotted {
+            commands.entity(entity).insert(Spotted);
+        } else if !visible && spotted {
+            commands.entity(entity).remove::<Spotted>();
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use bevy::prelude::*;
+use std::f32::consts::TAU;
+
+// How a group of bees lines up around the point it was sent to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Formation {
+    #[default]
+    Cluster, // Packed disc around the point
+    Line, // A row across the direction of travel
+    Wedge, // A V with its tip at the point
+    Ring, // A circle around the point, e.g. around something to attack
+}
+
+impl Formation {
+    // Offset of every slot from the destination, x across and y along the direction of travel
+    pub fn slots(self, count: usize, spacing: f32) -> Vec<Vec2> {
+        (0..count)
+            .map(|index| {
+                let i = index as f32;
+                match self {
+                    Formation::Cluster => {
+                        // Sunflower spiral, evenly packed whatever the count
+                        let golden_angle = TAU * (1.0 - 1.0 / 1.618_034);
+                        Vec2::from_angle(i * golden_angle) * spacing * i.sqrt()
+                    }
+                    Formation::Line => Vec2::new((i - (count - 1) as f32 / 2.0) * spacing, 0.0),
+                    Formation::Wedge => {
+                        // Tip first, then alternating left and right, one rank further back each pair
+                        let rank = ((index + 1) / 2) as f32;
+                        let side = if index % 2 == 1 { -1.0 } else { 1.0 };
+                        Vec2::new(side * rank * spacing, -rank * spacing)
+                    }
+                    Formation::Ring => {
+                        let radius = (spacing * count as f32 / TAU).max(spacing * 2.0);
+                        Vec2::from_angle(i * TAU / count as f32) * radius
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // Where each of `units` should go when the group is sent to `destination`
+    // The formation faces from the group's center towards the destination, and units take the slot on their own
+    // side of it so they don't cross paths on the way
+    pub fn assign(self, units: &[(Entity, Vec2)], destination: Vec2, spacing: f32) -> Vec<(Entity, Vec2)> {
+        if units.is_empty() {
+            return Vec::new();
+        }
+        let center = units.iter().map(|(_, position)| *position).sum::<Vec2>() / units.len() as f32;
+        let forward = (destination - center).try_normalize().unwrap_or(Vec2::Y);
+        // x axis of the formation, to the right of the direction of travel
+        let right = -forward.perp();
+        let across = |offset: Vec2| offset.dot(right);
+
+        let mut slots: Vec<Vec2> = self
+            .slots(units.len(), spacing)
+            .into_iter()
+            .map(|slot| right * slot.x + forward * slot.y)
+            .collect();
+        slots.sort_by(|a, b| across(*a).total_cmp(&across(*b)));
+        let mut units = units.to_vec();
+        units.sort_by(|(_, a), (_, b)| across(*a - center).total_cmp(&across(*b - center)));
+        units
+            .into_iter()
+            .zip(slots)
+            .map(|((unit, _), slot)| (unit, destination + slot))
+            .collect()
+    }
+}
@@ -0,0 +1,217 @@
+use bevy::prelude::*;
+use rand::Rng;
+use std::collections::VecDeque;
+
+use crate::bees::{BeeBundle, Collider, Team};
+use crate::caste::{Caste, Castes};
+use crate::combat::Health;
+use crate::fog::RevealRadius;
+use crate::interactions::{HighlightedEntities, Highlightable, MousePosition};
+use crate::level::LevelData;
+use crate::obstacles::Obstacle;
+use crate::simulation::GameRng;
+use crate::GameState;
+
+pub const MAX_TIER: u8 = 3;
+
+/// This plugin lets the player run their selected hives: Tab picks a caste, B queues a bee of it, U upgrades,
+/// right click moves the rally point. Storing honey and hatching the queued bees is part of the simulation
+pub struct HivePlugin;
+
+impl Plugin for HivePlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .init_resource::<SelectedCaste>()
+            .add_systems(Update, control_hives.run_if(in_state(GameState::Playing)))
+            .add_systems(Update, draw_rally_points.run_if(in_state(GameState::Playing)))
+            .add_systems(Update, scale_hives.run_if(in_state(GameState::Playing)))
+        ;
+    }
+}
+
+// A team's home: stores the honey its bees bring back and turns it into new bees
+#[derive(Component, Clone, Debug)]
+pub struct Hive {
+    pub honey: f32,
+    pub queue: VecDeque<Caste>, // Bees already paid for that have not hatched yet
+    pub progress: f32, // Seconds spent on the bee that hatches next
+    pub tier: u8,
+    pub rally_point: Vec2, // Where freshly hatched bees fly to
+}
+
+impl Hive {
+    pub fn new(rally_point: Vec2) -> Self {
+        Hive { honey: 0.0, queue: VecDeque::new(), progress: 0.0, tier: 1, rally_point }
+    }
+
+    // Seconds it takes to hatch one bee, every tier hatches faster
+    pub fn hatch_time(&self) -> f32 {
+        10.0 / self.tier as f32
+    }
+
+    pub fn max_queue(&self) -> usize {
+        5 * self.tier as usize
+    }
+
+    pub fn reveal_radius(&self, tile_size: f32) -> f32 {
+        (2.5 + self.tier as f32) * tile_size
+    }
+
+    // None once the hive is fully upgraded
+    pub fn upgrade_cost(&self) -> Option<f32> {
+        (self.tier < MAX_TIER).then(|| 100.0 * self.tier as f32)
+    }
+
+    // Pays for one more bee of `caste`, false if there is not enough honey or the queue is full
+    pub fn queue_bee(&mut self, caste: Caste, castes: &Castes) -> bool {
+        let cost = castes.get(caste).cost;
+        if self.honey < cost || self.queue.len() >= self.max_queue() {
+            return false;
+        }
+        self.honey -= cost;
+        self.queue.push_back(caste);
+        true
+    }
+
+    pub fn upgrade(&mut self) -> bool {
+        let Some(cost) = self.upgrade_cost() else {
+            return false;
+        };
+        if self.honey < cost {
+            return false;
+        }
+        self.honey -= cost;
+        self.tier += 1;
+        true
+    }
+}
+
+// Only the simulation side of a hive, the sprite is attached in `attach_level_sprites`
+#[derive(Bundle)]
+pub struct HiveBundle {
+    pub transform: TransformBundle,
+    pub hive: Hive,
+    pub team: Team,
+    pub highlightable: Highlightable,
+    pub reveal: RevealRadius, // Clears the fog around the hive, grows as it gets upgraded
+    pub collider: Collider,
+    pub obstacle: Obstacle,
+    pub health: Health,
+}
+
+impl HiveBundle {
+    // New bees gather right at the hive until the player picks another rally point
+    pub fn new(position: Vec3, team: Team, level: &LevelData) -> Self {
+        let hive = Hive::new(position.truncate());
+        HiveBundle {
+            transform: TransformBundle::from_transform(Transform::from_translation(position)),
+            reveal: RevealRadius(hive.reveal_radius(level.tile_size.x)),
+            hive,
+            team,
+            highlightable: Highlightable,
+            collider: Collider::new(400.0),
+            obstacle: Obstacle,
+            health: Health::new(500.0),
+        }
+    }
+}
+
+// Works through the spawn queue of every hive, new bees head for the rally point
+pub fn hatch_bees(
+    mut commands: Commands,
+    mut hives: Query<(&Transform, &Team, &mut Hive)>,
+    mut rng: ResMut<GameRng>,
+    castes: Res<Castes>,
+    time: Res<Time<Fixed>>,
+) {
+    for (transform, team, mut hive) in hives.iter_mut() {
+        if hive.queue.is_empty() {
+            hive.progress = 0.0;
+            continue;
+        }
+        hive.progress += time.delta_seconds();
+        if hive.progress < hive.hatch_time() {
+            continue;
+        }
+        hive.progress = 0.0;
+        let Some(caste) = hive.queue.pop_front() else {
+            continue;
+        };
+        let offset = Vec3::new(rng.gen_range(-200.0..=200.0), rng.gen_range(-200.0..=200.0), 0.0);
+        let position = transform.translation + offset;
+        commands.spawn(BeeBundle::new(position, hive.rally_point, *team, caste, &castes, &mut rng.0));
+        info!("Hive of team {} hatched a {:?}, {} left in the queue", team.0, caste, hive.queue.len());
+    }
+}
+
+// Upgraded hives see further
+pub fn apply_hive_tiers(mut hives: Query<(&Hive, &mut RevealRadius)>, level: Res<LevelData>) {
+    for (hive, mut reveal) in hives.iter_mut() {
+        let radius = hive.reveal_radius(level.tile_size.x);
+        if reveal.0 != radius {
+            reveal.0 = radius;
+        }
+    }
+}
+
+// The caste the player queues next with B
+#[derive(Resource, Debug, Default)]
+pub struct SelectedCaste(pub Caste);
+
+fn control_hives(
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mouse_position: Res<MousePosition>,
+    highlighted_entities: Res<HighlightedEntities>,
+    castes: Res<Castes>,
+    mut selected_caste: ResMut<SelectedCaste>,
+    mut q_hives: Query<(&Team, &mut Hive)>,
+) {
+    if keys.just_pressed(KeyCode::Tab) {
+        selected_caste.0 = selected_caste.0.next();
+    }
+    for entity in highlighted_entities.0.iter() {
+        let Ok((team, mut hive)) = q_hives.get_mut(*entity) else {
+            continue;
+        };
+        if *team != Team::PLAYER {
+            continue;
+        }
+        if keys.just_pressed(KeyCode::B) && !hive.queue_bee(selected_caste.0, &castes) {
+            info!("Not enough honey or the queue is full ({} honey, {} queued)", hive.honey, hive.queue.len());
+        }
+        if keys.just_pressed(KeyCode::U) {
+            if hive.upgrade() {
+                info!("Hive upgraded to tier {}", hive.tier);
+            } else {
+                info!("Hive can not be upgraded ({} honey, tier {})", hive.honey, hive.tier);
+            }
+        }
+        if buttons.just_pressed(MouseButton::Right) {
+            hive.rally_point = mouse_position.0;
+        }
+    }
+}
+
+fn draw_rally_points(
+    mut gizmos: Gizmos,
+    highlighted_entities: Res<HighlightedEntities>,
+    q_hives: Query<(&Transform, &Hive)>,
+) {
+    for entity in highlighted_entities.0.iter() {
+        if let Ok((transform, hive)) = q_hives.get(*entity) {
+            gizmos.line_2d(transform.translation.truncate(), hive.rally_point, Color::YELLOW);
+            gizmos.circle_2d(hive.rally_point, 100.0, Color::YELLOW);
+        }
+    }
+}
+
+// Bigger hive sprites for higher tiers
+fn scale_hives(mut q_hives: Query<(&Hive, &mut Transform), Changed<Hive>>) {
+    for (hive, mut transform) in q_hives.iter_mut() {
+        let scale = Vec3::splat(1.0 + 0.25 * (hive.tier - 1) as f32);
+        if transform.scale != scale {
+            transform.scale = scale;
+        }
+    }
+}
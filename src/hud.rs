@@ -0,0 +1,114 @@
+use bevy::prelude::*;
+
+use crate::bees::Team;
+use crate::caste::Castes;
+use crate::economy::Resources;
+use crate::hive::SelectedCaste;
+use crate::queen::{Queen, TeamDefeated};
+use crate::{GameState, HELP_FONT_SIZE, HELP_TEXT_PADDING, TEXT_COLOR};
+
+/// This plugin shows how much honey the player's hives have stored, and ends the game once a side lost its queen
+pub struct HudPlugin;
+impl Plugin for HudPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameState::Playing), setup_stockpile_text)
+            .add_systems(
+                Update,
+                update_stockpile_text.run_if(in_state(GameState::Playing)),
+            )
+            .add_systems(Update, check_outcome.run_if(in_state(GameState::Playing)))
+            .add_systems(OnEnter(GameState::Won), |commands: Commands, asset_server: Res<AssetServer>| {
+                show_outcome(commands, asset_server, "Victory! Every enemy queen has fallen")
+            })
+            .add_systems(OnEnter(GameState::Lost), |commands: Commands, asset_server: Res<AssetServer>| {
+                show_outcome(commands, asset_server, "Your queen has fallen")
+            });
+    }
+}
+
+#[derive(Component)]
+struct StockpileText;
+
+fn setup_stockpile_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        TextBundle::from_section(
+            "Honey: 0",
+            TextStyle {
+                font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+                font_size: HELP_FONT_SIZE,
+                color: TEXT_COLOR,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: HELP_TEXT_PADDING,
+            left: HELP_TEXT_PADDING,
+            ..default()
+        }),
+        StockpileText,
+    ));
+}
+
+fn update_stockpile_text(
+    resources: Res<Resources>,
+    selected_caste: Res<SelectedCaste>,
+    castes: Res<Castes>,
+    mut texts: Query<&mut Text, With<StockpileText>>,
+) {
+    if !resources.is_changed() && !selected_caste.is_changed() {
+        return;
+    }
+    let stockpile = resources.get(Team::PLAYER);
+    let caste = selected_caste.0;
+    for mut text in texts.iter_mut() {
+        text.sections[0].value = format!(
+            "Honey: {}  Next bee: {:?} ({} honey)",
+            stockpile.honey.floor(),
+            caste,
+            castes.get(caste).cost
+        );
+    }
+}
+
+// The player loses with their queen, and wins once no other team has one left
+fn check_outcome(
+    mut defeats: EventReader<TeamDefeated>,
+    queens: Query<&Team, With<Queen>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    let mut anyone_defeated = false;
+    for defeat in defeats.read() {
+        if defeat.team == Team::PLAYER {
+            next_state.set(GameState::Lost);
+            return;
+        }
+        anyone_defeated = true;
+    }
+    if anyone_defeated && queens.iter().all(|team| *team == Team::PLAYER) {
+        next_state.set(GameState::Won);
+    }
+}
+
+fn show_outcome(mut commands: Commands, asset_server: Res<AssetServer>, message: &str) {
+    commands
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Percent(100.0),
+                height: Val::Percent(100.0),
+                align_items: AlignItems::Center,
+                justify_content: JustifyContent::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|children| {
+            children.spawn(TextBundle::from_section(
+                message,
+                TextStyle {
+                    font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+                    font_size: 60.0,
+                    color: TEXT_COLOR,
+                },
+            ));
+        });
+}
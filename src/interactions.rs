@@ -1,6 +1,15 @@
 use bevy::window::PrimaryWindow;
 use bevy::prelude::*;
 use crate::GameState;
+use crate::bees::{Bee, BeeBehavior, BoidGroup, Team};
+use crate::boids::Position;
+use crate::combat::Health;
+use crate::orders::{Order, Orders};
+use crate::queen::Queen;
+use crate::tilemap::Flower;
+use crate::fog::Spotted;
+use crate::formation::Formation;
+use crate::navigation::FlightPath;
 
 use std::collections::HashSet;
 
@@ -14,7 +23,14 @@ impl Plugin for InteractionsPlugin {
             .add_systems(Update, show_mouse_location.run_if(in_state(GameState::Playing)))
             .insert_resource(MouseState(MouseStates::Default))
             .insert_resource(HighlightedEntities(HashSet::new()))
+            .init_resource::<ControlGroups>()
+            .init_resource::<SelectedFormation>()
             .add_systems(Update, mouse_state_manager.run_if(in_state(GameState::Playing)))
+            .add_systems(Update, control_group_hotkeys.run_if(in_state(GameState::Playing)))
+            .add_systems(Update, sync_highlighted.run_if(in_state(GameState::Playing)))
+            .add_systems(Update, pick_formation.run_if(in_state(GameState::Playing)))
+            .add_systems(Update, send_highlighted_bees.run_if(in_state(GameState::Playing)))
+            .add_systems(Update, draw_orders.run_if(in_state(GameState::Playing)))
             .add_systems(Update, draw_mouse_region.run_if(in_state(GameState::Playing)))
             .add_systems(Update, bloom_highlighted_entities.run_if(in_state(GameState::Playing)))
             .add_systems(Update, remove_bloom.run_if(in_state(GameState::Playing)))
@@ -46,7 +62,38 @@ pub struct Highlightable;
 #[derive(Component)]
 pub struct Highlighted;
 #[derive(Resource, Debug)]
-struct HighlightedEntities(HashSet<Entity>);
+pub struct HighlightedEntities(pub HashSet<Entity>);
+
+// Selections bound to the number keys 1..9
+#[derive(Resource, Debug, Default)]
+pub struct ControlGroups(pub [HashSet<Entity>; 9]);
+
+const GROUP_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+// The formation a group of bees takes when it is sent somewhere, picked with F1..F4
+#[derive(Resource, Debug, Default)]
+pub struct SelectedFormation(pub Formation);
+
+const FORMATION_KEYS: [(KeyCode, Formation); 4] = [
+    (KeyCode::F1, Formation::Cluster),
+    (KeyCode::F2, Formation::Line),
+    (KeyCode::F3, Formation::Wedge),
+    (KeyCode::F4, Formation::Ring),
+];
+
+// Seconds within which a second press on a group key centers the camera
+const DOUBLE_TAP_TIME: f32 = 0.3;
+// Half the size of the box a single click selects in
+const CLICK_SIZE: f32 = 100.0;
 
 #[derive(Component)]
 pub struct Clickable;
@@ -57,8 +104,13 @@ fn update_mouse_position(
     q_window: Query<&Window, With<PrimaryWindow>>,
     mut mouse_position: ResMut<MousePosition>,
 ) {
-    let (camera, camera_transform) = q_cam.single();
-    let window = q_window.single();
+    // Nothing to track without a window and camera, e.g. when running headless
+    let Ok((camera, camera_transform)) = q_cam.get_single() else {
+        return;
+    };
+    let Ok(window) = q_window.get_single() else {
+        return;
+    };
 
     if let Some(world_pos) = window
         .cursor_position()
@@ -74,21 +126,25 @@ fn show_mouse_location(mut gizmos: Gizmos, mouse_position: Res<MousePosition>) {
 }
 
 fn mouse_state_manager(
-    mut commands: Commands,
     buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
     mut mouse_state: ResMut<MouseState>,
     mouse_position: Res<MousePosition>,
     mut highlighted_entities: ResMut<HighlightedEntities>,
-    mut q_entities: Query<(Entity, &Transform, Option<&Highlighted>), With<Highlightable>>,
+    q_entities: Query<(Entity, &Transform, Option<&Team>), With<Highlightable>>,
 ) {
+    // Shift adds to the selection instead of replacing it, or takes a single clicked unit back out
+    let shift = keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight);
     for button in buttons.get_just_pressed() {
         // info!("{:?} is currently held down", button);
         // mouse_state.0 = MouseStates::MouseDown;
         match button {
             MouseButton::Left => {
                 mouse_state.0 = MouseStates::LeftDragging(mouse_position.0);
-                // TODO: Do something with the highlighted entities with an event at this point
-                highlighted_entities.0.clear();
+                // A new box replaces the old selection, orders are given with right click in `send_highlighted_bees`
+                if !shift {
+                    highlighted_entities.0.clear();
+                }
             },
             MouseButton::Right => mouse_state.0 = MouseStates::RightDragging(mouse_position.0),
             MouseButton::Middle => mouse_state.0 = MouseStates::MiddleDragging(mouse_position.0),
@@ -101,25 +157,28 @@ fn mouse_state_manager(
             // If it was previously in the dragging state
             if let MouseStates::LeftDragging(start_pos) = mouse_state.0 {
                 // Grab the mouse dragged square region
-                let min_x = start_pos.x.min(mouse_position.0.x);
-                let max_x = start_pos.x.max(mouse_position.0.x);
-                let min_y = start_pos.y.min(mouse_position.0.y);
-                let max_y = start_pos.y.max(mouse_position.0.y);
-                // For all entities with
-                for (entity, transform, highlighted) in q_entities.iter_mut() {
-                    let entity_pos = transform.translation;
-                    // Check if their transform is in the square region
-                    if entity_pos.x >= min_x && entity_pos.x <= max_x &&
-                        entity_pos.y >= min_y && entity_pos.y <= max_y {
-                        info!("Entity {:?} is inside the mouse square region.", entity);
-                        // Add them to the highlighted entities hashmap
+                let mut min = start_pos.min(mouse_position.0);
+                let mut max = start_pos.max(mouse_position.0);
+                // Barely dragged at all counts as a click on whatever is under the mouse
+                let clicked = (max - min).max_element() < CLICK_SIZE;
+                if clicked {
+                    min = mouse_position.0 - CLICK_SIZE;
+                    max = mouse_position.0 + CLICK_SIZE;
+                }
+                // Only the player's own units can be selected
+                let inside = q_entities
+                    .iter()
+                    .filter(|(_, _, team)| team.map_or(true, |team| *team == Team::PLAYER))
+                    .filter(|(_, transform, _)| {
+                        let entity_pos = transform.translation.truncate();
+                        entity_pos.cmpge(min).all() && entity_pos.cmple(max).all()
+                    })
+                    .map(|(entity, _, _)| entity);
+                for entity in inside {
+                    if shift && clicked && highlighted_entities.0.contains(&entity) {
+                        highlighted_entities.0.remove(&entity);
+                    } else {
                         highlighted_entities.0.insert(entity);
-
-                        if highlighted.is_none() {
-                            commands.entity(entity).insert(Highlighted);
-                        }
-                    } else if highlighted.is_some() {
-                        commands.entity(entity).remove::<Highlighted>();
                     }
                 }
             }
@@ -128,6 +187,205 @@ fn mouse_state_manager(
     }
 }
 
+// Keeps the `Highlighted` marker in line with `HighlightedEntities`, whoever changed it
+// Units that died are dropped from the selection
+fn sync_highlighted(
+    mut commands: Commands,
+    mut highlighted_entities: ResMut<HighlightedEntities>,
+    q_entities: Query<(Entity, Has<Highlighted>), With<Highlightable>>,
+) {
+    highlighted_entities.0.retain(|entity| q_entities.contains(*entity));
+    for (entity, highlighted) in q_entities.iter() {
+        let selected = highlighted_entities.0.contains(&entity);
+        if selected && !highlighted {
+            commands.entity(entity).insert(Highlighted);
+        } else if !selected && highlighted {
+            commands.entity(entity).remove::<Highlighted>();
+        }
+    }
+}
+
+// Ctrl+1..9 binds the selection to a group, 1..9 selects the group again and a quick second press centers the
+// camera on it. Ctrl+A selects every bee of the player
+fn control_group_hotkeys(
+    keys: Res<Input<KeyCode>>,
+    time: Res<Time>,
+    mut control_groups: ResMut<ControlGroups>,
+    mut highlighted_entities: ResMut<HighlightedEntities>,
+    mut last_recall: Local<Option<(usize, f32)>>,
+    q_bees: Query<(Entity, &Team), With<Bee>>,
+    q_positions: Query<&Transform, Without<Camera2d>>,
+    mut q_camera: Query<&mut Transform, With<Camera2d>>,
+) {
+    let ctrl = keys.pressed(KeyCode::ControlLeft) || keys.pressed(KeyCode::ControlRight);
+    if ctrl && keys.just_pressed(KeyCode::A) {
+        highlighted_entities.0 = q_bees
+            .iter()
+            .filter(|(_, team)| **team == Team::PLAYER)
+            .map(|(bee, _)| bee)
+            .collect();
+    }
+    for (index, key) in GROUP_KEYS.iter().enumerate() {
+        if !keys.just_pressed(*key) {
+            continue;
+        }
+        if ctrl {
+            control_groups.0[index] = highlighted_entities.0.clone();
+            info!("Bound {} units to group {}", control_groups.0[index].len(), index + 1);
+            continue;
+        }
+        // Dead units drop out of the group
+        control_groups.0[index].retain(|entity| q_positions.contains(*entity));
+        highlighted_entities.0 = control_groups.0[index].clone();
+        let now = time.elapsed_seconds();
+        let double_tap = matches!(*last_recall, Some((last, at)) if last == index && now - at < DOUBLE_TAP_TIME);
+        *last_recall = Some((index, now));
+        if !double_tap || control_groups.0[index].is_empty() {
+            continue;
+        }
+        let positions: Vec<Vec2> = control_groups.0[index]
+            .iter()
+            .filter_map(|entity| q_positions.get(*entity).ok())
+            .map(|transform| transform.translation.truncate())
+            .collect();
+        if let (Ok(mut camera), false) = (q_camera.get_single_mut(), positions.is_empty()) {
+            let center = positions.iter().sum::<Vec2>() / positions.len() as f32;
+            camera.translation = center.extend(camera.translation.z);
+        }
+    }
+}
+
+fn pick_formation(keys: Res<Input<KeyCode>>, mut selected: ResMut<SelectedFormation>) {
+    for (key, formation) in FORMATION_KEYS {
+        if keys.just_pressed(key) && selected.0 != formation {
+            selected.0 = formation;
+            info!("Bees now move in {:?} formation", formation);
+        }
+    }
+}
+
+// Right click orders every highlighted bee around depending on what is under the mouse:
+// an enemy to Attack, the queen to Escort, a flower to Forage, otherwise Move there
+// Several bees moving at once each get a slot of the selected formation around the point instead
+// Holding shift queues the order as a waypoint after the ones already given
+// E sends them exploring from where they are
+fn send_highlighted_bees(
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mouse_position: Res<MousePosition>,
+    highlighted_entities: Res<HighlightedEntities>,
+    formation: Res<SelectedFormation>,
+    q_groups: Query<&BoidGroup>,
+    q_flowers: Query<(Entity, &Transform), (With<Flower>, With<Spotted>)>,
+    q_queens: Query<(Entity, &Position, &Team), With<Queen>>,
+    q_enemies: Query<(Entity, Option<&Position>, &Transform, &Team), (With<Health>, With<Spotted>)>,
+    mut q_bees: Query<(&Transform, &mut BeeBehavior, &mut Orders)>,
+) {
+    if buttons.just_pressed(MouseButton::Right) {
+        let near_mouse = |position: Vec3| position.truncate().distance(mouse_position.0) < 150.0;
+        let clicked_enemy = q_enemies
+            .iter()
+            .find(|(_, position, transform, team)| {
+                **team != Team::PLAYER && near_mouse(position.map_or(transform.translation, |position| position.0))
+            })
+            .map(|(enemy, ..)| Order::Attack(enemy));
+        let clicked_queen = q_queens
+            .iter()
+            .find(|(_, position, team)| **team == Team::PLAYER && near_mouse(position.0))
+            .map(|(queen, _, _)| Order::Escort(queen));
+        let clicked_flower = q_flowers
+            .iter()
+            .find(|(_, transform)| near_mouse(transform.translation))
+            .map(|(flower, _)| Order::Forage(flower));
+        let order = clicked_enemy
+            .or(clicked_queen)
+            .or(clicked_flower)
+            .unwrap_or(Order::Move(mouse_position.0));
+        let queue = keys.pressed(KeyCode::ShiftLeft) || keys.pressed(KeyCode::ShiftRight);
+        let bees: Vec<(Entity, Vec2)> = highlighted_entities
+            .0
+            .iter()
+            .filter_map(|entity| {
+                let (transform, ..) = q_bees.get(*entity).ok()?;
+                Some((*entity, transform.translation.truncate()))
+            })
+            .collect();
+        let orders: Vec<(Entity, Order)> = match order {
+            Order::Move(destination) if bees.len() > 1 => {
+                // Slots are spaced so neighbors just stop pushing each other away
+                let spacing = q_groups
+                    .iter()
+                    .find(|group| group.id == Team::PLAYER.0)
+                    .map_or(150.0, |group| group.separation_radius);
+                formation
+                    .0
+                    .assign(&bees, destination, spacing)
+                    .into_iter()
+                    .map(|(bee, slot)| (bee, Order::March(slot)))
+                    .collect()
+            }
+            _ => bees.iter().map(|(bee, _)| (*bee, order)).collect(),
+        };
+        for (entity, order) in orders {
+            if let Ok((_, mut behavior, mut bee_orders)) = q_bees.get_mut(entity) {
+                bee_orders.give(order, queue, &mut behavior);
+            }
+        }
+    }
+    if keys.just_pressed(KeyCode::E) {
+        for entity in highlighted_entities.0.iter() {
+            if let Ok((transform, mut behavior, mut orders)) = q_bees.get_mut(*entity) {
+                orders.0.clear();
+                *behavior = BeeBehavior::Exploring {
+                    origin: transform.translation.truncate(),
+                    wander: transform.rotation.to_euler(EulerRot::XYZ).2 + std::f32::consts::FRAC_PI_2,
+                };
+            }
+        }
+    }
+}
+
+// Shows where the highlighted bees are headed, around walls and through all of their queued waypoints
+fn draw_orders(
+    mut gizmos: Gizmos,
+    highlighted_entities: Res<HighlightedEntities>,
+    q_bees: Query<(&Transform, &BeeBehavior, &Orders, &FlightPath)>,
+    q_targets: Query<&Transform>,
+) {
+    let target_position = |order: Order| match order {
+        Order::Move(destination) | Order::March(destination) => Some(destination),
+        Order::Forage(target) | Order::Attack(target) | Order::Escort(target) => {
+            q_targets.get(target).ok().map(|transform| transform.translation.truncate())
+        }
+    };
+    for entity in highlighted_entities.0.iter() {
+        let Ok((transform, behavior, orders, path)) = q_bees.get(*entity) else {
+            continue;
+        };
+        let mut from = transform.translation.truncate();
+        for waypoint in path.waypoints.iter() {
+            gizmos.line_2d(from, *waypoint, Color::YELLOW);
+            from = *waypoint;
+        }
+        let current = match *behavior {
+            BeeBehavior::Traveling(destination) => Some(Order::Move(destination)),
+            BeeBehavior::Marching(slot) => Some(Order::March(slot)),
+            BeeBehavior::Foraging { flower } => Some(Order::Forage(flower)),
+            BeeBehavior::Attacking(target) => Some(Order::Attack(target)),
+            BeeBehavior::Escorting(queen) => Some(Order::Escort(queen)),
+            _ => None,
+        };
+        for order in current.into_iter().chain(orders.0.iter().copied()) {
+            let Some(to) = target_position(order) else {
+                continue;
+            };
+            let color = if matches!(order, Order::Attack(_)) { Color::RED } else { Color::GREEN };
+            gizmos.line_2d(from, to, color);
+            from = to;
+        }
+    }
+}
+
 fn draw_mouse_region
 (
     mouse_state: Res<MouseState>,
@@ -0,0 +1,145 @@
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use crate::ai::Difficulty;
+use crate::bees::{BeeBundle, Collider, Team};
+use crate::caste::{Caste, Castes};
+use crate::fog::FogOfWar;
+use crate::hive::HiveBundle;
+use crate::navigation::NavGrid;
+use crate::queen::QueenBundle;
+use crate::simulation::GameRng;
+use crate::tilemap::Flower;
+
+// The level being played, whether it was generated or loaded from an LDtk file
+// The camera bounds, the boid groups, fog of war and navigation all size themselves from it
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct LevelData {
+    pub level_width: f32,
+    pub level_height: f32,
+    pub map_size: TilemapSize, // In tiles
+    pub tile_size: Vec2, // In world units
+}
+
+impl LevelData {
+    pub fn new(map_size: TilemapSize, tile_size: Vec2) -> Self {
+        LevelData {
+            level_width: tile_size.x * map_size.x as f32,
+            level_height: tile_size.y * map_size.y as f32,
+            map_size,
+            tile_size,
+        }
+    }
+
+    pub fn contains(&self, pos: &TilePos) -> bool {
+        pos.x < self.map_size.x && pos.y < self.map_size.y
+    }
+
+    // Where a tile is kept in per tile storage like the cells of the `FogGrid` or `NavGrid`, one row after another
+    pub fn tile_index(&self, pos: &TilePos) -> usize {
+        (pos.y * self.map_size.x + pos.x) as usize
+    }
+
+    pub fn tile_count(&self) -> usize {
+        (self.map_size.x * self.map_size.y) as usize
+    }
+
+    // The tile under a world position, tiles are laid out from the origin
+    pub fn tile_pos(&self, world_pos: Vec2) -> Option<TilePos> {
+        let tile = (world_pos / self.tile_size).floor();
+        if tile.x < 0.0 || tile.y < 0.0 || tile.x >= self.map_size.x as f32 || tile.y >= self.map_size.y as f32 {
+            return None;
+        }
+        Some(TilePos { x: tile.x as u32, y: tile.y as u32 })
+    }
+
+    // Center of a tile in world coordinates
+    pub fn world_pos(&self, pos: &TilePos) -> Vec2 {
+        (Vec2::new(pos.x as f32, pos.y as f32) + 0.5) * self.tile_size
+    }
+}
+
+// What a level is made of before anything is spawned, in world coordinates
+// Filled in by the generator in `generate_level` or from the entities and IntGrid of an LDtk level
+#[derive(Debug, Default)]
+pub struct LevelLayout {
+    pub hives: Vec<(Vec2, Team)>,
+    pub queens: Vec<(Vec2, Team)>,
+    pub flowers: Vec<(Vec2, u8)>, // Position and which of the flower textures to draw
+    pub walls: Vec<TilePos>,
+}
+
+// Spawns the simulated side of a level from its layout: hives with their first bees, queens and flowers
+// A team with a queen but no hive gets one right next to her. Other teams than the player's start with the
+// `Difficulty` head start. Also inserts the level, its fog of war and navigation grid, and the `GameRng` that
+// everything after this keeps drawing from
+pub fn populate_level(
+    commands: &mut Commands,
+    level: LevelData,
+    mut layout: LevelLayout,
+    mut rng: StdRng,
+    difficulty: &Difficulty,
+    castes: &Castes,
+) {
+    commands.insert_resource(level);
+    commands.insert_resource(FogOfWar::new(level));
+    let mut nav_grid = NavGrid::new(level);
+    for wall in layout.walls.iter() {
+        nav_grid.set_wall(wall, true);
+    }
+    commands.insert_resource(nav_grid);
+
+    let homeless: Vec<(Vec2, Team)> = layout
+        .queens
+        .iter()
+        .filter(|(_, team)| !layout.hives.iter().any(|(_, hive_team)| hive_team == team))
+        .map(|(position, team)| (*position + Vec2::new(-400.0, 0.0), *team))
+        .collect();
+    layout.hives.extend(homeless);
+
+    for (position, team) in layout.hives.iter() {
+        let mut hive = HiveBundle::new(position.extend(2.0), *team, &level);
+        if *team != Team::PLAYER {
+            hive.hive.honey = difficulty.head_start();
+        }
+        commands.spawn(hive);
+        spawn_swarm(commands, &mut rng, castes, position.extend(2.0), *team);
+        info!("Spawned hive of team {} at {:?}", team.0, position);
+    }
+    for (position, team) in layout.queens.iter() {
+        commands.spawn(QueenBundle::new(position.extend(5.0), *team));
+        info!("Spawned queen of team {} at {:?}", team.0, position);
+    }
+    for (position, kind) in layout.flowers.iter() {
+        commands.spawn((
+            TransformBundle::from_transform(Transform::from_translation(position.extend(1.0))),
+            Flower::new(*kind),
+            Collider::new(300.0),
+        ));
+    }
+    info!("Populated level on {:?}", *difficulty);
+
+    commands.insert_resource(GameRng(rng));
+}
+
+// The initial 5 bees of a team around its hive, all workers but one scout
+fn spawn_swarm(commands: &mut Commands, rng: &mut StdRng, castes: &Castes, hive_position: Vec3, team: Team) {
+    for caste in [Caste::Scout, Caste::Worker, Caste::Worker, Caste::Worker, Caste::Worker] {
+        let bee_offset_x = rng.gen_range(-500.0..=500.0); // Adjust the offset range as needed
+        let bee_offset_y = rng.gen_range(-500.0..=500.0);
+        commands.spawn(BeeBundle::new(
+            Vec3::new(
+                hive_position.x + bee_offset_x,
+                hive_position.y + bee_offset_y,
+                hive_position.z,
+            ),
+            Vec2::new(hive_position.x, hive_position.y),
+            team,
+            caste,
+            castes,
+            rng,
+        ));
+    }
+}
@@ -1,4 +1,6 @@
 #![allow(clippy::type_complexity)]
+// Bevy systems take every query and resource they need as a parameter
+#![allow(clippy::too_many_arguments)]
 
 const HELP_FONT_SIZE: f32 = 1.0;
 const TEXT_COLOR: Color = Color::Rgba {
@@ -10,6 +12,7 @@ const TEXT_COLOR: Color = Color::Rgba {
 const HELP_TEXT_PADDING: Val = Val::Px(15.0);
 
 mod actions;
+mod ai;
 mod audio;
 mod loading;
 mod menu;
@@ -17,11 +20,25 @@ mod player;
 
 mod camera;
 mod bees;
+mod behavior;
 mod boids;
+mod caste;
+mod combat;
 mod debug;
+mod economy;
+mod fog;
+mod formation;
+mod hive;
+mod hud;
+mod level;
+mod queen;
 mod world;
 mod interactions;
+mod navigation;
+mod obstacles;
+mod orders;
 mod tilemap;
+pub mod simulation;
 
 use crate::actions::ActionsPlugin;
 // use crate::audio::InternalAudioPlugin;
@@ -31,9 +48,13 @@ use crate::menu::MenuPlugin;
 // use crate::player::PlayerPlugin;
 
 use crate::bees::BeesPlugin;
-// use crate::world::WorldPlugin;
+use crate::world::WorldPlugin;
 use crate::camera::CameraPlugin;
+use crate::hive::HivePlugin;
+use crate::hud::HudPlugin;
+use crate::queen::QueenPlugin;
 use crate::tilemap::MapPlugin;
+use crate::simulation::{SimulationPlugin, SimulationSet};
 
 use bevy::app::App;
 #[cfg(debug_assertions)]
@@ -57,6 +78,9 @@ enum GameState {
     Playing,
     // Here the menu is drawn and waiting for player interaction
     Menu,
+    // The simulation stops once the game is decided, the last frame stays on screen under the outcome
+    Won,
+    Lost,
 }
 
 pub struct GamePlugin;
@@ -71,11 +95,17 @@ impl Plugin for GamePlugin {
             // PlayerPlugin,
             CameraPlugin,
             MapPlugin,
-            // WorldPlugin,
+            WorldPlugin,
             InteractionsPlugin,
+            HudPlugin,
+            HivePlugin,
+            QueenPlugin,
             BeesPlugin,
+            SimulationPlugin::default(),
             DebugPlugin,
-        ));
+        ))
+        .configure_sets(Update, SimulationSet.run_if(in_state(GameState::Playing)))
+        .configure_sets(FixedUpdate, SimulationSet.run_if(in_state(GameState::Playing)));
 
         #[cfg(debug_assertions)]
         {
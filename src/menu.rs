@@ -1,18 +1,21 @@
 use bevy::core_pipeline::bloom::BloomSettings;
+use crate::ai::Difficulty;
 use crate::loading::TextureAssets;
 use crate::GameState;
 use bevy::prelude::*;
 
 pub struct MenuPlugin;
 
-/// This plugin is responsible for the game menu (containing only one button...)
+/// This plugin is responsible for the game menu (play, the level to play on and the difficulty of the AI opponent)
 /// The menu is only drawn during the State `GameState::Menu` and is removed when that state is exited
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(OnEnter(GameState::Menu), setup_menu)
             .add_systems(Update, click_play_button.run_if(in_state(GameState::Menu)))
+            .add_systems(Update, update_difficulty_label.run_if(in_state(GameState::Menu)))
+            .add_systems(Update, update_level_label.run_if(in_state(GameState::Menu)))
             .add_systems(OnExit(GameState::Menu), cleanup_menu)
-            .insert_resource(NextLevel(""));
+            .init_resource::<NextLevel>();
     }
 }
 
@@ -34,7 +37,12 @@ impl Default for ButtonColors {
 #[derive(Component)]
 struct Menu;
 
-fn setup_menu(mut commands: Commands, textures: Res<TextureAssets>) {
+fn setup_menu(
+    mut commands: Commands,
+    textures: Res<TextureAssets>,
+    difficulty: Res<Difficulty>,
+    next_level: Res<NextLevel>,
+) {
     info!("menu");
     commands.spawn((
         Camera2dBundle {
@@ -115,7 +123,6 @@ fn setup_menu(mut commands: Commands, textures: Res<TextureAssets>) {
                     },
                     button_colors,
                     ChangeState(GameState::Playing),
-                    StartLevel("demo.ldtk"),
                 ))
                 .with_children(|parent| {
                     parent.spawn(TextBundle::from_section(
@@ -127,6 +134,68 @@ fn setup_menu(mut commands: Commands, textures: Res<TextureAssets>) {
                         },
                     ));
                 });
+            let level_colors = ButtonColors::default();
+            children
+                .spawn((
+                    ButtonBundle {
+                        style: Style {
+                            width: Val::Px(240.0),
+                            height: Val::Px(40.0),
+                            margin: UiRect::top(Val::Px(10.0)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        background_color: level_colors.normal.into(),
+                        ..Default::default()
+                    },
+                    level_colors,
+                    CycleLevel,
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section(
+                            next_level.label(),
+                            TextStyle {
+                                font_size: 24.0,
+                                color: Color::rgb(0.9, 0.9, 0.9),
+                                ..default()
+                            },
+                        ),
+                        LevelLabel,
+                    ));
+                });
+            let difficulty_colors = ButtonColors::default();
+            children
+                .spawn((
+                    ButtonBundle {
+                        style: Style {
+                            width: Val::Px(240.0),
+                            height: Val::Px(40.0),
+                            margin: UiRect::top(Val::Px(10.0)),
+                            justify_content: JustifyContent::Center,
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        background_color: difficulty_colors.normal.into(),
+                        ..Default::default()
+                    },
+                    difficulty_colors,
+                    CycleDifficulty,
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section(
+                            format!("Difficulty: {:?}", *difficulty),
+                            TextStyle {
+                                font_size: 24.0,
+                                color: Color::rgb(0.9, 0.9, 0.9),
+                                ..default()
+                            },
+                        ),
+                        DifficultyLabel,
+                    ));
+                });
         });
     commands
         .spawn((
@@ -230,15 +299,45 @@ struct ChangeState(GameState);
 #[derive(Component)]
 struct OpenLink(&'static str);
 
+// Clicking steps through the levels: a generated one, or one of the LDtk files
+#[derive(Component)]
+struct CycleLevel;
+
+#[derive(Component)]
+struct LevelLabel;
+
+// Clicking steps through the difficulty of the AI opponent
 #[derive(Component)]
-struct StartLevel(&'static str);
+struct CycleDifficulty;
 
-#[derive(Resource)]
-pub struct NextLevel(pub &'static str);
+#[derive(Component)]
+struct DifficultyLabel;
+
+// LDtk files that can be played, besides the generated level
+const LEVELS: [&str; 2] = ["demo.ldtk", "world.ldtk"];
+
+// The level to play next: the LDtk file to load, or nothing for a generated one
+#[derive(Resource, Debug, Default)]
+pub struct NextLevel(pub Option<&'static str>);
+
+impl NextLevel {
+    pub fn next(&self) -> Self {
+        let index = self.0.and_then(|level| LEVELS.iter().position(|known| *known == level));
+        match index {
+            None => NextLevel(Some(LEVELS[0])),
+            Some(index) => NextLevel(LEVELS.get(index + 1).copied()),
+        }
+    }
+
+    fn label(&self) -> String {
+        format!("Level: {}", self.0.unwrap_or("Generated"))
+    }
+}
 
 fn click_play_button(
     mut next_state: ResMut<NextState<GameState>>,
     mut next_level: ResMut<NextLevel>,
+    mut difficulty: ResMut<Difficulty>,
     mut interaction_query: Query<
         (
             &Interaction,
@@ -246,18 +345,22 @@ fn click_play_button(
             &ButtonColors,
             Option<&ChangeState>,
             Option<&OpenLink>,
-            Option<&StartLevel>,
+            Has<CycleLevel>,
+            Has<CycleDifficulty>,
         ),
         (Changed<Interaction>, With<Button>),
     >,
 ) {
-    for (interaction, mut color, button_colors, change_state, open_link, start_level) in
+    for (interaction, mut color, button_colors, change_state, open_link, cycle_level, cycle_difficulty) in
         &mut interaction_query
     {
         match *interaction {
             Interaction::Pressed => {
-                if let Some(level) = start_level {
-                    next_level.0 = level.0;
+                if cycle_level {
+                    *next_level = next_level.next();
+                }
+                if cycle_difficulty {
+                    *difficulty = difficulty.next();
                 }
                 if let Some(state) = change_state {
                     next_state.set(state.0.clone());
@@ -277,6 +380,24 @@ fn click_play_button(
     }
 }
 
+fn update_difficulty_label(difficulty: Res<Difficulty>, mut labels: Query<&mut Text, With<DifficultyLabel>>) {
+    if !difficulty.is_changed() {
+        return;
+    }
+    for mut text in labels.iter_mut() {
+        text.sections[0].value = format!("Difficulty: {:?}", *difficulty);
+    }
+}
+
+fn update_level_label(next_level: Res<NextLevel>, mut labels: Query<&mut Text, With<LevelLabel>>) {
+    if !next_level.is_changed() {
+        return;
+    }
+    for mut text in labels.iter_mut() {
+        text.sections[0].value = next_level.label();
+    }
+}
+
 fn cleanup_menu(mut commands: Commands, menu: Query<Entity, With<Menu>>) {
     for entity in menu.iter() {
         commands.entity(entity).despawn_recursive();
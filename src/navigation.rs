@@ -0,0 +1,319 @@
+use bevy::prelude::*;
+use bevy::utils::{HashMap, HashSet};
+use bevy_ecs_tilemap::prelude::*;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
+
+use crate::boids::Position;
+use crate::level::LevelData;
+
+// Costs of a straight and a diagonal step between tiles, integers so the open set can be ordered
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+// Cached paths are dropped all at once past this many, as they are cheap to plan again
+const MAX_CACHED_PATHS: usize = 1024;
+
+// Which tiles of the level can be flown through, keyed by `TilePos` like the `FogGrid`
+// `revision` goes up whenever a wall is added or removed, so paths planned before can be thrown away
+#[derive(Resource, Debug)]
+pub struct NavGrid {
+    pub level: LevelData,
+    pub revision: u32,
+    walls: Vec<bool>,
+}
+
+impl NavGrid {
+    // A level without any walls
+    pub fn new(level: LevelData) -> Self {
+        NavGrid { level, revision: 0, walls: vec![false; level.tile_count()] }
+    }
+
+    // Anything off the map counts as a wall
+    pub fn is_wall(&self, pos: &TilePos) -> bool {
+        !self.level.contains(pos) || self.walls[self.level.tile_index(pos)]
+    }
+
+    pub fn set_wall(&mut self, pos: &TilePos, wall: bool) {
+        if !self.level.contains(pos) {
+            return;
+        }
+        let index = self.level.tile_index(pos);
+        if self.walls[index] != wall {
+            self.walls[index] = wall;
+            self.revision += 1;
+        }
+    }
+
+    // Whether the straight line between two points stays clear of walls
+    // Samples a few points per tile, which is plenty for bees flying between tile centers
+    pub fn line_of_sight(&self, from: Vec2, to: Vec2) -> bool {
+        let step = self.level.tile_size.min_element() / 4.0;
+        let samples = (from.distance(to) / step).ceil().max(1.0) as u32;
+        (0..=samples).all(|sample| {
+            let point = from.lerp(to, sample as f32 / samples as f32);
+            self.level.tile_pos(point).is_some_and(|pos| !self.is_wall(&pos))
+        })
+    }
+
+    // Open tiles around `pos`, diagonals only when they don't cut the corner of a wall
+    fn neighbors(&self, pos: TilePos) -> impl Iterator<Item = (TilePos, u32)> + '_ {
+        let open = move |dx: i32, dy: i32| {
+            let (x, y) = (pos.x as i32 + dx, pos.y as i32 + dy);
+            if x < 0 || y < 0 {
+                return None;
+            }
+            let next = TilePos { x: x as u32, y: y as u32 };
+            (!self.is_wall(&next)).then_some(next)
+        };
+        [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (-1, 1), (1, -1), (1, 1)]
+            .into_iter()
+            .filter_map(move |(dx, dy)| {
+                let next = open(dx, dy)?;
+                if dx != 0 && dy != 0 {
+                    open(dx, 0)?;
+                    open(0, dy)?;
+                    return Some((next, DIAGONAL_COST));
+                }
+                Some((next, STRAIGHT_COST))
+            })
+    }
+
+    // A* from tile to tile, returning the tile centers to fly through after leaving `from`
+    // The path is shortened to the corners it has to go around, an empty one means flying straight is fine
+    pub fn find_path(&self, from: TilePos, to: TilePos) -> Option<Vec<Vec2>> {
+        if self.is_wall(&to) {
+            return None;
+        }
+        if self.line_of_sight(self.level.world_pos(&from), self.level.world_pos(&to)) {
+            return Some(Vec::new());
+        }
+        // Octile distance, never more than the actual cost
+        let heuristic = |pos: TilePos| octile_distance(pos, to);
+        let mut open = BinaryHeap::new();
+        let mut came_from: HashMap<TilePos, TilePos> = HashMap::new();
+        let mut cost: HashMap<TilePos, u32> = HashMap::new();
+        cost.insert(from, 0);
+        open.push(Reverse((heuristic(from), from.x, from.y)));
+        while let Some(Reverse((_, x, y))) = open.pop() {
+            let current = TilePos { x, y };
+            if current == to {
+                return Some(self.smooth(from, self.walk_back(&came_from, to)));
+            }
+            let current_cost = cost[&current];
+            for (next, step) in self.neighbors(current) {
+                let next_cost = current_cost + step;
+                if cost.get(&next).is_some_and(|known| *known <= next_cost) {
+                    continue;
+                }
+                cost.insert(next, next_cost);
+                came_from.insert(next, current);
+                open.push(Reverse((next_cost + heuristic(next), next.x, next.y)));
+            }
+        }
+        None
+    }
+
+    fn walk_back(&self, came_from: &HashMap<TilePos, TilePos>, to: TilePos) -> Vec<TilePos> {
+        let mut tiles = vec![to];
+        while let Some(previous) = came_from.get(tiles.last().unwrap()) {
+            tiles.push(*previous);
+        }
+        tiles.reverse();
+        tiles
+    }
+
+    // Skips every tile that can be seen past, keeping only the corners
+    fn smooth(&self, from: TilePos, tiles: Vec<TilePos>) -> Vec<Vec2> {
+        let mut waypoints = Vec::new();
+        let mut anchor = self.level.world_pos(&from);
+        for pair in tiles.windows(2).skip(1) {
+            let (corner, next) = (self.level.world_pos(&pair[0]), self.level.world_pos(&pair[1]));
+            if !self.line_of_sight(anchor, next) {
+                waypoints.push(corner);
+                anchor = corner;
+            }
+        }
+        waypoints
+    }
+}
+
+// Cost of the shortest way between two tiles when nothing is in the way
+fn octile_distance(a: TilePos, b: TilePos) -> u32 {
+    let dx = a.x.abs_diff(b.x);
+    let dy = a.y.abs_diff(b.y);
+    STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+}
+
+// Paths planned so far between two tiles, shared by every bee making the same trip
+// Thrown away as soon as the `NavGrid` changes
+#[derive(Resource, Debug, Default)]
+pub struct PathCache {
+    revision: u32,
+    paths: HashMap<(TilePos, TilePos), Option<Vec<Vec2>>>,
+}
+
+impl PathCache {
+    pub fn get(&mut self, grid: &NavGrid, from: TilePos, to: TilePos) -> Option<Vec<Vec2>> {
+        if self.revision != grid.revision || self.paths.len() > MAX_CACHED_PATHS {
+            self.paths.clear();
+            self.revision = grid.revision;
+        }
+        self.paths.entry((from, to)).or_insert_with(|| grid.find_path(from, to)).clone()
+    }
+}
+
+// Where a bee is flying to and the corners it has to go around to get there
+// Behaviors set the `goal` every tick through `FlightPath::towards`, `plan_paths` fills in the waypoints
+#[derive(Component, Clone, Debug, Default)]
+pub struct FlightPath {
+    pub goal: Option<Vec2>,
+    pub waypoints: VecDeque<Vec2>,
+    planned: Option<(TilePos, u32)>, // Goal tile and grid revision the waypoints were planned for
+}
+
+impl FlightPath {
+    // Heads for `goal`, returning the waypoint to fly to first if there is a wall in the way
+    pub fn towards(&mut self, goal: Vec2) -> Option<Vec2> {
+        self.goal = Some(goal);
+        self.waypoints.front().copied()
+    }
+}
+
+// Plans a path for every bee whose goal moved to another tile, whose level changed, or that got pushed off its way
+// Waypoints are dropped as they are reached, and goals are cleared for the behaviors to set again next tick
+pub fn plan_paths(grid: Res<NavGrid>, mut cache: ResMut<PathCache>, mut bees: Query<(&Position, &mut FlightPath)>) {
+    let reach = grid.level.tile_size.min_element() / 2.0;
+    for (position, mut path) in bees.iter_mut() {
+        let position = position.0.truncate();
+        let goal_tile = path.goal.take().and_then(|goal| grid.level.tile_pos(goal));
+        let (Some(here), Some(goal_tile)) = (grid.level.tile_pos(position), goal_tile) else {
+            path.waypoints.clear();
+            path.planned = None;
+            continue;
+        };
+        while path.waypoints.front().is_some_and(|waypoint| waypoint.distance(position) < reach) {
+            path.waypoints.pop_front();
+        }
+        let lost = path.waypoints.front().is_some_and(|waypoint| !grid.line_of_sight(position, *waypoint));
+        if path.planned == Some((goal_tile, grid.revision)) && !lost {
+            continue;
+        }
+        // Nowhere to go means flying straight at it, as bees did before there were walls
+        path.waypoints = cache.get(&grid, here, goal_tile).unwrap_or_default().into();
+        path.planned = Some((goal_tile, grid.revision));
+    }
+}
+
+// Which way to fly from every tile of the level to reach one goal tile, shared by every bee sent there
+// Built from an integration field of the cost to the goal, so one field serves a whole swarm for the price of a
+// single search. Tiles with a clear way to the goal, or none at all, have no direction and bees fly straight
+#[derive(Debug)]
+pub struct FlowField {
+    pub goal: TilePos,
+    directions: Vec<Option<Vec2>>,
+}
+
+impl FlowField {
+    pub fn new(grid: &NavGrid, goal: TilePos) -> Self {
+        // Integration field: cheapest cost from every tile to the goal, Dijkstra outwards from it
+        let mut cost = vec![u32::MAX; grid.level.tile_count()];
+        let mut open = BinaryHeap::new();
+        if !grid.is_wall(&goal) {
+            cost[grid.level.tile_index(&goal)] = 0;
+            open.push(Reverse((0, goal.x, goal.y)));
+        }
+        while let Some(Reverse((current_cost, x, y))) = open.pop() {
+            let current = TilePos { x, y };
+            if current_cost > cost[grid.level.tile_index(&current)] {
+                continue;
+            }
+            for (next, step) in grid.neighbors(current) {
+                let index = grid.level.tile_index(&next);
+                if current_cost + step < cost[index] {
+                    cost[index] = current_cost + step;
+                    open.push(Reverse((cost[index], next.x, next.y)));
+                }
+            }
+        }
+        // Line of sight to the goal, spread outwards from it ring by ring. The straight line from a tile leaves
+        // through its neighbor towards the goal along the longer axis, then maybe the diagonal one next to that,
+        // so the tile sees the goal when both of those do. An exact diagonal squeezes between two open tiles
+        let mut visible = vec![false; cost.len()];
+        let size = grid.level.map_size;
+        let mut tiles: Vec<TilePos> = (0..size.y).flat_map(|y| (0..size.x).map(move |x| TilePos { x, y })).collect();
+        tiles.sort_by_key(|pos| pos.x.abs_diff(goal.x).max(pos.y.abs_diff(goal.y)));
+        for pos in tiles {
+            if grid.is_wall(&pos) {
+                continue;
+            }
+            let (dx, dy) = (goal.x as i32 - pos.x as i32, goal.y as i32 - pos.y as i32);
+            let (sx, sy) = (dx.signum(), dy.signum());
+            let toward = |x: i32, y: i32| TilePos { x: (pos.x as i32 + x) as u32, y: (pos.y as i32 + y) as u32 };
+            let sees = |next: TilePos| visible[grid.level.tile_index(&next)];
+            let clear = match dx.abs().cmp(&dy.abs()) {
+                _ if pos == goal => true,
+                Ordering::Greater => sees(toward(sx, 0)) && (sy == 0 || sees(toward(sx, sy))),
+                Ordering::Less => sees(toward(0, sy)) && (sx == 0 || sees(toward(sx, sy))),
+                Ordering::Equal => {
+                    sees(toward(sx, sy)) && !grid.is_wall(&toward(sx, 0)) && !grid.is_wall(&toward(0, sy))
+                }
+            };
+            visible[grid.level.tile_index(&pos)] = clear;
+        }
+        // Flow field: every tile points at its cheapest neighbor, tiles that see the goal fly straight
+        let mut directions = vec![None; cost.len()];
+        for y in 0..grid.level.map_size.y {
+            for x in 0..grid.level.map_size.x {
+                let pos = TilePos { x, y };
+                let index = grid.level.tile_index(&pos);
+                if cost[index] == u32::MAX || visible[index] {
+                    continue;
+                }
+                directions[index] = grid
+                    .neighbors(pos)
+                    .min_by_key(|(next, _)| cost[grid.level.tile_index(next)])
+                    .and_then(|(next, _)| (grid.level.world_pos(&next) - grid.level.world_pos(&pos)).try_normalize());
+            }
+        }
+        FlowField { goal, directions }
+    }
+
+    pub fn direction(&self, grid: &NavGrid, pos: &TilePos) -> Option<Vec2> {
+        if !grid.level.contains(pos) {
+            return None;
+        }
+        self.directions[grid.level.tile_index(pos)]
+    }
+}
+
+// Flow fields of the orders bees are following right now, one per goal tile
+// Built the first time a bee looks one up, dropped once nobody did for a whole tick or when the `NavGrid` changes
+#[derive(Resource, Debug, Default)]
+pub struct FlowFields {
+    revision: u32,
+    fields: HashMap<TilePos, FlowField>,
+    in_use: HashSet<TilePos>,
+}
+
+impl FlowFields {
+    // Which way to fly from `from` to get around the walls towards `goal`, nothing when flying straight is fine
+    pub fn direction(&mut self, grid: &NavGrid, from: Vec2, goal: Vec2) -> Option<Vec2> {
+        if self.revision != grid.revision {
+            self.fields.clear();
+            self.revision = grid.revision;
+        }
+        let (here, goal) = (grid.level.tile_pos(from)?, grid.level.tile_pos(goal)?);
+        self.in_use.insert(goal);
+        self.fields
+            .entry(goal)
+            .or_insert_with(|| FlowField::new(grid, goal))
+            .direction(grid, &here)
+    }
+}
+
+// Forgets the flow fields of orders that every bee is done with
+pub fn prune_flow_fields(mut flow_fields: ResMut<FlowFields>) {
+    let flow_fields = &mut *flow_fields;
+    let in_use = std::mem::take(&mut flow_fields.in_use);
+    flow_fields.fields.retain(|goal, _| in_use.contains(goal));
+}
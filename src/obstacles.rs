@@ -0,0 +1,146 @@
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::TilePos;
+use quadtree::prelude::*;
+use quadtree::quadtree::tree::QuadTree;
+
+use crate::bees::Collider;
+use crate::navigation::NavGrid;
+use crate::level::LevelData;
+
+// Something big that doesn't move and that bees fly around instead of through, like a hive
+// Flowers aren't obstacles, foragers are meant to land on them
+// Its size is the one of its `Collider`. Walls of the `NavGrid` are obstacles without needing an entity
+#[derive(Component, Clone, Copy, Debug, Default)]
+pub struct Obstacle;
+
+// A square in the `ObstacleIndex`, the same shape `Collider::into_region` gives
+#[derive(Clone, Copy, Debug)]
+pub struct StaticBody {
+    pub entity: Option<Entity>, // Nothing for wall tiles
+    pub center: Vec2,
+    pub half_size: Vec2,
+}
+
+// Every static obstacle of the level in a quadtree of its own, apart from the per team ones of the moving boids
+// Those are rebuilt every tick, this one only when an obstacle comes or goes
+#[derive(Resource)]
+pub struct ObstacleIndex {
+    pub graph: QuadTree<StaticBody>,
+    pub count: u32,
+}
+
+impl ObstacleIndex {
+    pub fn new(min: Vec2, max: Vec2) -> Self {
+        ObstacleIndex { graph: QuadTree::new(square_region(min, max)), count: 0 }
+    }
+
+    pub fn insert(&mut self, body: StaticBody) {
+        self.graph.insert(square_region(body.center - body.half_size, body.center + body.half_size), body);
+        self.count += 1;
+    }
+
+    // Every obstacle a boid at `position` would fly into within `look_ahead` along `heading`
+    // The query covers the whole stretch, grown by the boid's own half size
+    pub fn ahead(&self, position: Vec2, heading: Vec2, look_ahead: f32, half_size: f32) -> Vec<StaticBody> {
+        let end = position + heading * look_ahead;
+        let no_exclude = vec![];
+        self.graph
+            .query(&square_region(position.min(end) - half_size, position.max(end) + half_size), &no_exclude)
+            .into_iter()
+            .copied()
+            .collect()
+    }
+}
+
+fn square_region(min: Vec2, max: Vec2) -> region::Region {
+    region::Region::new(coord::Coord::from_f32(min.x, min.y), coord::Coord::from_f32(max.x, max.y))
+}
+
+// Rebuilds the `ObstacleIndex` from the walls and every `Obstacle` whenever any of them changed
+pub fn build_obstacle_index(
+    mut commands: Commands,
+    level_data: Res<LevelData>,
+    nav_grid: Option<Res<NavGrid>>,
+    obstacles: Query<(Entity, &Collider, &Transform), With<Obstacle>>,
+    changed: Query<(), (With<Obstacle>, Or<(Added<Obstacle>, Changed<Collider>)>)>,
+    mut removed: RemovedComponents<Obstacle>,
+    index: Option<Res<ObstacleIndex>>,
+) {
+    let walls_changed = nav_grid.as_ref().is_some_and(|grid| grid.is_changed());
+    let removed = removed.read().count() > 0;
+    if index.is_some() && !level_data.is_changed() && !walls_changed && !removed && changed.is_empty() {
+        return;
+    }
+    let mut index = ObstacleIndex::new(Vec2::ZERO, Vec2::new(level_data.level_width, level_data.level_height));
+    if let Some(grid) = nav_grid {
+        for x in 0..grid.level.map_size.x {
+            for y in 0..grid.level.map_size.y {
+                let pos = TilePos { x, y };
+                if grid.is_wall(&pos) {
+                    let half_size = grid.level.tile_size / 2.0;
+                    index.insert(StaticBody { entity: None, center: grid.level.world_pos(&pos), half_size });
+                }
+            }
+        }
+    }
+    for (entity, collider, transform) in obstacles.iter() {
+        let half_size = Vec2::splat(collider.radius / 2.0);
+        index.insert(StaticBody { entity: Some(entity), center: transform.translation.truncate(), half_size });
+    }
+    info!("Indexed {} static obstacles", index.count);
+    commands.insert_resource(index);
+}
+
+// Reynolds' obstacle avoidance: finds the closest obstacle the boid's heading runs into within `look_ahead`
+// and steers sideways away from its center, harder the sooner it would hit. A boid already inside one is pushed
+// straight out. Obstacles are grown by the boid's own `half_size` so it steers clear of the edges
+pub fn obstacle_steering(
+    position: Vec2,
+    heading: Vec2,
+    look_ahead: f32,
+    half_size: f32,
+    obstacles: impl IntoIterator<Item = StaticBody>,
+) -> Vec2 {
+    let mut closest: Option<(f32, StaticBody)> = None;
+    for obstacle in obstacles {
+        let half_size = obstacle.half_size + half_size;
+        let offset = position - obstacle.center;
+        if offset.abs().cmplt(half_size).all() {
+            return offset.try_normalize().unwrap_or(-heading);
+        }
+        let Some(hit) = ray_hits_box(position, heading, obstacle.center, half_size) else {
+            continue;
+        };
+        if hit <= look_ahead && closest.map_or(true, |(closest, _)| hit < closest) {
+            closest = Some((hit, obstacle));
+        }
+    }
+    let Some((hit, obstacle)) = closest else {
+        return Vec2::ZERO;
+    };
+    // Away from the center, across the heading. Dead center on it picks a side
+    let away = position + heading * hit - obstacle.center;
+    let lateral = away - heading * away.dot(heading);
+    let lateral = lateral.try_normalize().unwrap_or(heading.perp());
+    lateral * (1.0 - hit / look_ahead.max(1.0))
+}
+
+// How far along the unit `direction` a ray from `origin` enters the box, slab test
+fn ray_hits_box(origin: Vec2, direction: Vec2, center: Vec2, half_size: Vec2) -> Option<f32> {
+    let (min, max) = (center - half_size, center + half_size);
+    let mut enter = 0.0_f32;
+    let mut exit = f32::INFINITY;
+    for axis in 0..2 {
+        if direction[axis].abs() <= f32::EPSILON {
+            if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let a = (min[axis] - origin[axis]) / direction[axis];
+        let b = (max[axis] - origin[axis]) / direction[axis];
+        enter = enter.max(a.min(b));
+        exit = exit.min(a.max(b));
+    }
+    (enter <= exit).then_some(enter)
+}
@@ -0,0 +1,75 @@
+use bevy::prelude::*;
+use std::collections::VecDeque;
+
+use crate::bees::BeeBehavior;
+use crate::behavior::BeeState;
+
+// Something a unit was told to do, turned into a `BeeBehavior` once it gets to it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Order {
+    Move(Vec2),
+    March(Vec2), // Move to this bee's own slot of a formation
+    Forage(Entity),
+    Attack(Entity),
+    Escort(Entity),
+}
+
+impl Order {
+    pub fn behavior(self) -> BeeBehavior {
+        match self {
+            Order::Move(destination) => BeeBehavior::Traveling(destination),
+            Order::March(slot) => BeeBehavior::Marching(slot),
+            Order::Forage(flower) => BeeBehavior::Foraging { flower },
+            Order::Attack(target) => BeeBehavior::Attacking(target),
+            Order::Escort(queen) => BeeBehavior::Escorting(queen),
+        }
+    }
+
+    // Foraging and escorting go on until told otherwise, nothing queued behind them would ever start
+    pub fn is_endless(self) -> bool {
+        matches!(self, Order::Forage(_) | Order::Escort(_))
+    }
+}
+
+// Bees that keep at it until told otherwise: exploring, foraging and flying the harvest home, escorting
+fn is_endless(state: BeeState) -> bool {
+    matches!(state, BeeState::Exploring | BeeState::Foraging | BeeState::Returning | BeeState::Escorting)
+}
+
+// Orders waiting for the current one to finish, shift-queued by the player
+#[derive(Component, Clone, Debug, Default)]
+pub struct Orders(pub VecDeque<Order>);
+
+impl Orders {
+    // Replace whatever the unit was doing, or queue behind it when `queue` is set (shift held)
+    // An order queued behind one that never finishes takes its place instead
+    pub fn give(&mut self, order: Order, queue: bool, behavior: &mut BeeBehavior) {
+        if !queue {
+            self.0.clear();
+            *behavior = order.behavior();
+            return;
+        }
+        if self.0.back().is_some_and(|last| last.is_endless()) {
+            self.0.pop_back();
+        }
+        let state = behavior.state();
+        if self.0.is_empty() && (state == BeeState::Idle || is_endless(state)) {
+            // Nothing to wait for
+            *behavior = order.behavior();
+        } else {
+            self.0.push_back(order);
+        }
+    }
+}
+
+// Units that finished what they were doing move on to their next queued order
+pub fn follow_orders(mut units: Query<(&mut Orders, &mut BeeBehavior)>) {
+    for (mut orders, mut behavior) in units.iter_mut() {
+        if behavior.state() != BeeState::Idle {
+            continue;
+        }
+        if let Some(order) = orders.0.pop_front() {
+            *behavior = order.behavior();
+        }
+    }
+}
@@ -0,0 +1,198 @@
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+use crate::bees::{BoidGroup, Collider, Steering, Team, Velocity};
+use crate::behavior::arrive;
+use crate::boids::{Position, PreviousPosition};
+use crate::combat::Health;
+use crate::fog::RevealRadius;
+use crate::hive::{Hive, HiveBundle};
+use crate::interactions::{HighlightedEntities, Highlightable, MousePosition};
+use crate::level::LevelData;
+use crate::GameState;
+
+// Honey a new hive costs, paid by the richest hive of the queen's team
+pub const FOUNDING_COST: f32 = 100.0;
+// No hive can be founded closer than this to another one, of any team
+pub fn min_hive_distance(level: &LevelData) -> f32 {
+    3.0 * level.tile_size.x
+}
+// The queen is slower than her workers
+const QUEEN_THROTTLE: f32 = 0.5;
+
+/// This plugin lets the player move their selected queen with right click, F makes her found a hive where she lands
+/// Moving, founding and what happens when she dies are part of the simulation
+pub struct QueenPlugin;
+
+impl Plugin for QueenPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_systems(Update, control_queens.run_if(in_state(GameState::Playing)))
+            .add_systems(Update, draw_queen_destinations.run_if(in_state(GameState::Playing)))
+        ;
+    }
+}
+
+// The heart of a team: she moves on her own orders, founds new hives, and the team is lost without her
+#[derive(Component, Clone, Debug, Default)]
+pub struct Queen {
+    pub destination: Option<Vec2>,
+    pub founding: bool, // Found a hive as soon as she stops
+}
+
+// Only the simulation side of a queen, the sprite is attached in `attach_level_sprites`
+// Not a `Boid`: bees don't flock with her, they escort her through `BeeBehavior::Escorting`
+#[derive(Bundle)]
+pub struct QueenBundle {
+    pub transform: TransformBundle,
+    pub position: Position,
+    pub previous_position: PreviousPosition,
+    pub queen: Queen,
+    pub team: Team,
+    pub highlightable: Highlightable,
+    pub collider: Collider,
+    pub velocity: Velocity,
+    pub steering: Steering,
+    pub reveal: RevealRadius,
+    pub health: Health,
+}
+
+impl QueenBundle {
+    pub fn new(position: Vec3, team: Team) -> Self {
+        QueenBundle {
+            transform: TransformBundle::from_transform(Transform::from_translation(position)),
+            position: Position(position),
+            previous_position: PreviousPosition(position),
+            queen: Queen::default(),
+            team,
+            highlightable: Highlightable,
+            collider: Collider::new(50.0),
+            velocity: Velocity(Vec3::ZERO),
+            steering: Steering::default(),
+            reveal: RevealRadius(1200.0),
+            health: Health::new(200.0),
+        }
+    }
+}
+
+// A team whose queen died, sent once per team
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TeamDefeated {
+    pub team: Team,
+}
+
+// Flies every queen towards her destination, and keeps her still once she got there
+pub fn steer_queens(
+    mut queens: Query<(&Position, &Velocity, &Team, &mut Queen, &mut Steering)>,
+    groups: Query<&BoidGroup>,
+) {
+    for (position, velocity, team, mut queen, mut steering) in queens.iter_mut() {
+        // She goes where she is told, not where the swarm goes
+        steering.flocking = 0.0;
+        steering.separation = 0.0;
+        let Some(group) = groups.iter().find(|group| group.id == team.0) else {
+            steering.throttle = 0.0;
+            continue;
+        };
+        let Some(destination) = queen.destination else {
+            steering.throttle = 0.0;
+            continue;
+        };
+        let (force, throttle, arrived) = arrive(position.0, velocity.0.normalize_or_zero(), destination, group);
+        if arrived {
+            queen.destination = None;
+            steering.throttle = 0.0;
+            continue;
+        }
+        steering.force += force;
+        steering.throttle = steering.throttle.min(throttle * QUEEN_THROTTLE);
+    }
+}
+
+// A queen that was told to found a hive does so once she stands still, if there is room and honey for it
+pub fn found_hives(
+    mut commands: Commands,
+    mut queens: Query<(&Position, &Team, &mut Queen)>,
+    mut hives: Query<(&Transform, &Team, &mut Hive)>,
+    level: Res<LevelData>,
+) {
+    let min_distance = min_hive_distance(&level);
+    for (position, team, mut queen) in queens.iter_mut() {
+        if !queen.founding || queen.destination.is_some() {
+            continue;
+        }
+        queen.founding = false;
+        let crowded = hives
+            .iter()
+            .any(|(transform, _, _)| transform.translation.truncate().distance(position.0.truncate()) < min_distance);
+        if crowded {
+            info!("Team {} can not found a hive this close to another one", team.0);
+            continue;
+        }
+        let richest = hives
+            .iter_mut()
+            .filter(|(_, hive_team, _)| *hive_team == team)
+            .max_by(|(_, _, a), (_, _, b)| a.honey.total_cmp(&b.honey));
+        match richest {
+            Some((_, _, mut hive)) if hive.honey >= FOUNDING_COST => hive.honey -= FOUNDING_COST,
+            _ => {
+                info!("Team {} has no hive with {} honey to found a new one", team.0, FOUNDING_COST);
+                continue;
+            }
+        }
+        let hive_position = position.0.truncate().extend(2.0);
+        commands.spawn(HiveBundle::new(hive_position, *team, &level));
+        info!("Team {} founded a hive at {:?}", team.0, hive_position);
+    }
+}
+
+// A team is defeated when the last of its queens is gone
+pub fn detect_defeated_teams(
+    queens: Query<&Team, With<Queen>>,
+    mut reigning: Local<HashSet<u32>>,
+    mut defeats: EventWriter<TeamDefeated>,
+) {
+    let alive: HashSet<u32> = queens.iter().map(|team| team.0).collect();
+    for team in reigning.difference(&alive) {
+        info!("The queen of team {} is dead", team);
+        defeats.send(TeamDefeated { team: Team(*team) });
+    }
+    *reigning = alive;
+}
+
+fn control_queens(
+    buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mouse_position: Res<MousePosition>,
+    highlighted_entities: Res<HighlightedEntities>,
+    mut q_queens: Query<(&Team, &mut Queen)>,
+) {
+    for entity in highlighted_entities.0.iter() {
+        let Ok((team, mut queen)) = q_queens.get_mut(*entity) else {
+            continue;
+        };
+        if *team != Team::PLAYER {
+            continue;
+        }
+        if buttons.just_pressed(MouseButton::Right) {
+            queen.destination = Some(mouse_position.0);
+        }
+        if keys.just_pressed(KeyCode::F) {
+            queen.founding = true;
+        }
+    }
+}
+
+fn draw_queen_destinations(mut gizmos: Gizmos, q_queens: Query<(&Position, &Team, &Queen)>) {
+    for (position, team, queen) in q_queens.iter() {
+        if *team != Team::PLAYER {
+            continue;
+        }
+        let Some(destination) = queen.destination else {
+            continue;
+        };
+        let color = if queen.founding { Color::GOLD } else { Color::WHITE };
+        gizmos.line_2d(position.0.truncate(), destination, color);
+        gizmos.circle_2d(destination, 150.0, color);
+    }
+}
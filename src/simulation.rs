@@ -0,0 +1,230 @@
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::ai::run_ai;
+use crate::bees::BeeArrived;
+use crate::combat::{resolve_stings, Killed};
+use crate::behavior::{run_behaviors, track_state_changes, BeeStateChanged, BehaviorWeights};
+use crate::economy::{deposit_at_hives, harvest_flowers, regrow_flowers, tally_stockpiles};
+use crate::hive::{apply_hive_tiers, hatch_bees};
+use crate::navigation::{plan_paths, prune_flow_fields};
+use crate::obstacles::build_obstacle_index;
+use crate::orders::follow_orders;
+use crate::queen::{detect_defeated_teams, found_hives, steer_queens};
+use crate::fog::{explore_fog, update_fog_grid, update_spotted};
+use crate::boids::{
+    build_or_update_quadtree, create_boid_groups, move_system, store_previous_positions,
+    update_boids,
+};
+
+// Everything needed to drive the simulation without the rest of the game
+pub use crate::ai::{AiAction, Difficulty};
+pub use crate::bees::{
+    Bee, BeeArrived, BeeBehavior, BeeBundle, BeeVariant, BoidGroup, BorderMode, Collider, Steering,
+    Team, Velocity,
+};
+pub use crate::behavior::{BeeState, SteeringWeights};
+pub use crate::caste::{Caste, CasteStats, Castes};
+pub use crate::boids::{Boid, Position, PreviousPosition};
+pub use crate::combat::{Attack, Health, Killed};
+pub use crate::economy::{Cargo, Resources, Stockpile};
+pub use crate::fog::{Exploration, FogGrid, FogOfWar, FogState, RevealRadius, Spotted};
+pub use crate::hive::{Hive, HiveBundle};
+pub use crate::navigation::{FlightPath, FlowField, FlowFields, NavGrid, PathCache};
+pub use crate::obstacles::{Obstacle, ObstacleIndex, StaticBody};
+pub use crate::level::{populate_level, LevelData, LevelLayout};
+pub use crate::tilemap::{generate_level, Flower};
+pub use crate::orders::{Order, Orders};
+pub use crate::queen::{Queen, QueenBundle, TeamDefeated};
+
+/// This plugin runs the boid/bee simulation and nothing else: no window, camera, textures or input.
+/// It works on top of `MinimalPlugins`, so it can be stepped headless, e.g. in CI:
+///
+/// ```ignore
+/// let mut app = App::new();
+/// app.add_plugins((MinimalPlugins, SimulationPlugin::default()))
+///     // Every update moves the clock on by exactly one tick instead of following the wall clock
+///     .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(1.0 / 90.0)))
+///     .add_systems(Startup, generate_level);
+/// for _ in 0..1000 {
+///     app.update();
+/// }
+/// ```
+///
+/// All of the simulation runs in `FixedUpdate` at `tick_rate` ticks per second, so N ticks always give the same
+/// result no matter the frame rate. Rendering is layered on by `BeesPlugin` and `MapPlugin`, which attach sprites
+/// to the entities spawned here and interpolate their transforms between ticks.
+pub struct SimulationPlugin {
+    pub tick_rate: f64,
+}
+
+impl Default for SimulationPlugin {
+    fn default() -> Self {
+        SimulationPlugin { tick_rate: 90.0 }
+    }
+}
+
+/// Seed for every random choice made by the world generation and the simulation
+/// Insert it before adding the plugin to replay a run, otherwise a random one is picked (and logged)
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameSeed(pub u64);
+
+impl Default for GameSeed {
+    fn default() -> Self {
+        GameSeed(rand::random())
+    }
+}
+
+/// The one random number generator the simulation draws from, seeded from `GameSeed` and handed over by
+/// `populate_level` once the level, generated or loaded from LDtk, is spawned
+/// Systems that need randomness take it as `ResMut<GameRng>` instead of calling `rand::thread_rng()`
+#[derive(Resource, Deref, DerefMut)]
+pub struct GameRng(pub StdRng);
+
+/// All simulation systems live in this set, so the game can gate them on its own `GameState`
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SimulationSet;
+
+/// The phases of one simulation tick, run in this order in `FixedUpdate`
+/// Rules that push boids around add to their `Steering` during `Steer`, which `update_boids` then blends with the
+/// flocking in `Flock`. Systems within `Steer` must be chained so the forces always add up in the same order
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BoidSet {
+    Index, // Spatial indexes are rebuilt from the last tick's positions
+    Interact, // Everything that happens on contact, found through those indexes
+    Steer,
+    Flock,
+    Move,
+    Reveal, // Fog of war catches up with where everything moved to
+}
+
+impl Plugin for SimulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameSeed>()
+            .insert_resource(Time::<Fixed>::from_hz(self.tick_rate))
+            .init_resource::<BehaviorWeights>()
+            .init_resource::<Resources>()
+            .init_resource::<Difficulty>()
+            .init_resource::<Castes>()
+            .init_resource::<PathCache>()
+            .init_resource::<FlowFields>()
+            .register_type::<Castes>()
+            .register_type::<BehaviorWeights>()
+            .add_event::<BeeArrived>()
+            .add_event::<BeeStateChanged>()
+            .add_event::<TeamDefeated>()
+            .add_event::<Killed>()
+            .configure_sets(
+                FixedUpdate,
+                (BoidSet::Index, BoidSet::Interact, BoidSet::Steer, BoidSet::Flock, BoidSet::Move, BoidSet::Reveal)
+                    .chain()
+                    .in_set(SimulationSet),
+            )
+            .add_systems(
+                FixedUpdate,
+                (
+                    (
+                        create_boid_groups.run_if(resource_exists::<LevelData>()),
+                        build_obstacle_index.run_if(resource_exists::<LevelData>()),
+                        store_previous_positions,
+                        build_or_update_quadtree,
+                    )
+                        .chain()
+                        .in_set(BoidSet::Index),
+                    (
+                        regrow_flowers,
+                        harvest_flowers,
+                        deposit_at_hives,
+                        found_hives.run_if(resource_exists::<LevelData>()),
+                        hatch_bees.run_if(resource_exists::<GameRng>()),
+                        apply_hive_tiers.run_if(resource_exists::<LevelData>()),
+                        tally_stockpiles,
+                        resolve_stings,
+                        detect_defeated_teams,
+                    )
+                        .chain()
+                        .in_set(BoidSet::Interact),
+                    (
+                        run_ai.run_if(resource_exists::<FogOfWar>()),
+                        follow_orders,
+                        steer_queens,
+                        run_behaviors.run_if(resource_exists::<GameRng>()),
+                        plan_paths.run_if(resource_exists::<NavGrid>()),
+                        prune_flow_fields,
+                        explore_fog.run_if(resource_exists::<FogOfWar>()),
+                        track_state_changes,
+                    )
+                        .chain()
+                        .in_set(BoidSet::Steer),
+                    update_boids.in_set(BoidSet::Flock),
+                    move_system.in_set(BoidSet::Move),
+                    (update_fog_grid, update_spotted)
+                        .chain()
+                        .run_if(resource_exists::<FogOfWar>())
+                        .in_set(BoidSet::Reveal),
+                ),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::time::TimeUpdateStrategy;
+    use std::time::Duration;
+
+    #[derive(Resource, Default)]
+    struct Ticks(u32);
+
+    // The simulation on its own, with a clock that moves on by `frame` every update
+    fn headless(seed: u64, frame: Duration) -> App {
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, SimulationPlugin::default()))
+            .insert_resource(GameSeed(seed))
+            .insert_resource(TimeUpdateStrategy::ManualDuration(frame))
+            .init_resource::<Ticks>()
+            .add_systems(Startup, generate_level)
+            .add_systems(FixedUpdate, |mut ticks: ResMut<Ticks>| ticks.0 += 1);
+        // Spawns the level, the clock only starts counting from the next update
+        app.update();
+        app
+    }
+
+    fn bee_positions(app: &mut App) -> Vec<(Entity, Vec3)> {
+        let mut bees = app.world.query_filtered::<(Entity, &Position), With<Bee>>();
+        let mut positions: Vec<(Entity, Vec3)> =
+            bees.iter(&app.world).map(|(entity, position)| (entity, position.0)).collect();
+        positions.sort_by_key(|(entity, _)| *entity);
+        positions
+    }
+
+    #[test]
+    fn bees_move_headless() {
+        let mut app = headless(1, Duration::from_secs_f64(1.0 / 60.0));
+        let before = bee_positions(&mut app);
+        assert!(!before.is_empty());
+        for _ in 0..60 {
+            app.update();
+        }
+        assert!(app.world.resource::<Ticks>().0 > 0);
+        let after = bee_positions(&mut app);
+        let moved = before.iter().zip(after.iter()).filter(|((_, a), (_, b))| a.distance(*b) > 1.0).count();
+        assert!(moved > 0, "no bee moved in {} ticks", app.world.resource::<Ticks>().0);
+    }
+
+    #[test]
+    fn same_ticks_same_result_at_any_frame_rate() {
+        // Three and two ticks a frame, both whole numbers of the 90 Hz tick
+        let mut slow = headless(7, Duration::from_nanos(33_333_333));
+        let mut fast = headless(7, Duration::from_nanos(22_222_222));
+        for _ in 0..60 {
+            slow.update();
+        }
+        for _ in 0..90 {
+            fast.update();
+        }
+        assert_eq!(slow.world.resource::<Ticks>().0, 180);
+        assert_eq!(fast.world.resource::<Ticks>().0, 180);
+        assert_eq!(bee_positions(&mut slow), bee_positions(&mut fast));
+    }
+}
@@ -3,44 +3,109 @@ use bevy::prelude::*;
 use bevy_ecs_tilemap::prelude::*;
 use crate::loading::TextureAssets;
 
-use rand::Rng;
-use crate::bees::{Bee, BeeBehavior, Collider, Velocity};
-use crate::boids::Boid;
-use crate::interactions::Highlightable;
-
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use crate::bees::Team;
+use crate::ai::Difficulty;
+use crate::caste::Castes;
+use crate::simulation::GameSeed;
+use crate::fog::{FogOfWar, FogState, Spotted};
+use crate::hive::Hive;
+use crate::level::{populate_level, LevelData, LevelLayout};
+use crate::menu::NextLevel;
+use crate::queen::Queen;
+
+/// This plugin draws the level: the ground and fog tilemaps plus the sprites of the hive, queen and flowers
+/// The level itself is generated by `generate_level` without needing any of the rendering, unless an LDtk level
+/// was picked in the menu, which the `WorldPlugin` loads instead
 pub struct MapPlugin;
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
         app
             .add_plugins(TilemapPlugin)
-            .add_systems(OnEnter(GameState::Playing), setup_level)
+            .add_systems(OnEnter(GameState::Playing), (generate_level, draw_ground).run_if(generated_level))
+            .add_systems(Update, setup_level.run_if(in_state(GameState::Playing).and_then(resource_added::<LevelData>())))
+            .add_systems(Update, attach_level_sprites.run_if(in_state(GameState::Playing)))
+            .add_systems(Update, draw_fog.run_if(in_state(GameState::Playing).and_then(resource_exists_and_changed::<FogOfWar>())))
+            .add_systems(Update, hide_unspotted.run_if(in_state(GameState::Playing)))
         ;
     }
 }
 
-#[derive(Resource, Debug)]
-pub struct LevelData {
-    pub level_height: f32,
-    pub level_width: f32,
+pub const MAP_SIZE: TilemapSize = TilemapSize { x: 32, y: 32 };
+pub const TILE_SIZE: TilemapTileSize = TilemapTileSize { x: 720.0, y: 720.0 };
+
+// Mixed into the `GameSeed` for the ground tiles' own random stream
+const GROUND_STREAM: u64 = 0x9E37_79B9_7F4A_7C15;
+
+fn generated_level(next_level: Res<NextLevel>) -> bool {
+    next_level.0.is_none()
 }
 
-fn setup_level(
+// Generates an open meadow: the player's hive and queen, flowers all over, and the enemy colony at least half
+// the map away. Everything is spawned by `populate_level`, just like the levels loaded from LDtk
+pub fn generate_level(mut commands: Commands, seed: Res<GameSeed>, difficulty: Res<Difficulty>, castes: Res<Castes>) {
+    let map_size = MAP_SIZE;
+    let tile_size = TILE_SIZE;
+    let grid_size: TilemapGridSize = tile_size.into();
+    let level = LevelData::new(map_size, Vec2::new(tile_size.x, tile_size.y));
+
+    info!("Generating level with seed {}", seed.0);
+    let mut rng = StdRng::seed_from_u64(seed.0);
+    let mut layout = LevelLayout::default();
+
+    // Spawn the hive
+    let max_x = (map_size.x * tile_size.x as u32) - 720;
+    let max_y = (map_size.y * tile_size.y as u32) - 720;
+
+    let beehive_position = Vec3::new(
+        ((rng.gen::<u32>() % max_x) as f32 / tile_size.x * tile_size.x + 360.) as f32,
+        ((rng.gen::<u32>() % max_y) as f32 / tile_size.y * tile_size.y + 360.) as f32,
+        2.0,
+    );
+    layout.hives.push((beehive_position.truncate(), Team::PLAYER));
+
+    // Spawn the bee queen away from the hive
+    let bee_queen_position = get_random_position_away_from_hive(&mut rng, beehive_position, max_x, max_y, grid_size, 720);
+    layout.queens.push((bee_queen_position.truncate(), Team::PLAYER));
+
+    // Spawn flowers randomly within the map bounds
+    const NUM_FLOWERS: usize = 20;
+    for _ in 0..NUM_FLOWERS {
+        let flower_type = rng.gen::<u8>() % 4; // Assuming you have 4 flower types
+        let flower_position = get_random_position(&mut rng, max_x, max_y, grid_size);
+        layout.flowers.push((flower_position.truncate(), flower_type));
+    }
+
+    // The enemy colony starts at least half the map away from the player
+    let enemy_hive_position = get_random_position_away_from_hive(&mut rng, beehive_position, max_x, max_y, grid_size, max_x / 2);
+    layout.hives.push((enemy_hive_position.truncate(), Team::ENEMY));
+    layout.queens.push((enemy_hive_position.truncate() + Vec2::new(400.0, 0.0), Team::ENEMY));
+
+    // Everything after generation keeps drawing from the same stream
+    populate_level(&mut commands, level, layout, rng, &difficulty, &castes);
+}
+
+// Draws the ground tilemap of the generated level, LDtk levels bring their own
+fn draw_ground(
     mut commands: Commands,
     // asset_server: Res<AssetServer>,
     textures: Res<TextureAssets>,
-    mut q_camera: Query<&mut Transform, With<Camera2d>>,
+    seed: Res<GameSeed>,
 
     #[cfg(all(not(feature = "atlas"), feature = "render"))]
     array_texture_loader: Res<ArrayTextureLoader>,
 ) {
     let texture_handle: Handle<Image> = textures.ground.clone();
 
-    let map_size = TilemapSize { x: 32, y: 32 };
+    let map_size = MAP_SIZE;
 
     let tilemap_entity = commands.spawn_empty().id();
 
     let mut tile_storage = TileStorage::empty(map_size);
-    let mut rng = rand::thread_rng();
+    // Own stream from the same seed, so drawing the map never changes what the simulation rolls, and the ground
+    // doesn't repeat the rolls that placed the hives and flowers
+    let mut rng = StdRng::seed_from_u64(seed.0 ^ GROUND_STREAM);
 
     for x in 0..map_size.x {
         for y in 0..map_size.y {
@@ -57,9 +122,8 @@ fn setup_level(
         }
     }
 
-    let tile_size = TilemapTileSize { x: 720.0, y: 720.0 };
+    let tile_size = TILE_SIZE;
     let grid_size: TilemapGridSize = tile_size.into();
-    commands.insert_resource(LevelData { level_height: grid_size.y * map_size.y as f32, level_width: grid_size.x * map_size.x as f32 });
     let map_type = TilemapType::default();
 
     commands.entity(tilemap_entity).insert(TilemapBundle {
@@ -84,6 +148,23 @@ fn setup_level(
             ..Default::default()
         });
     }
+}
+
+// Covers whatever level got loaded with fog tiles and centers the camera on the player's hive
+// The fog texture is drawn at the level's tile size, however big that is
+fn setup_level(
+    mut commands: Commands,
+    textures: Res<TextureAssets>,
+    level: Res<LevelData>,
+    mut q_camera: Query<&mut Transform, With<Camera2d>>,
+    q_hive: Query<(&Transform, &Team), (With<Hive>, Without<Camera2d>)>,
+) {
+    let map_size = level.map_size;
+    let tile_size = TILE_SIZE;
+    let grid_size: TilemapGridSize = tile_size.into();
+    let map_type = TilemapType::default();
+    let scale = level.tile_size / Vec2::new(tile_size.x, tile_size.y);
+
     // Spawn fog
     // Create a separate tilemap for fog
     let fog_tilemap_entity = commands.spawn_empty().id();
@@ -94,178 +175,124 @@ fn setup_level(
         for y in 0..map_size.y {
             let fog_tile_pos = TilePos { x, y };
             let fog_tile_entity = commands
-                .spawn(TileBundle {
-                    position: fog_tile_pos,
-                    tilemap_id: TilemapId(fog_tilemap_entity),
-                    texture_index: TileTextureIndex(0), // Set fog texture index
-                    ..Default::default()
-                })
+                .spawn((
+                    TileBundle {
+                        position: fog_tile_pos,
+                        tilemap_id: TilemapId(fog_tilemap_entity),
+                        texture_index: TileTextureIndex(0), // Set fog texture index
+                        ..Default::default()
+                    },
+                    FogTile,
+                ))
                 .id();
             fog_tile_storage.set(&fog_tile_pos, fog_tile_entity);
         }
     }
 
     // Insert fog tilemap bundle
-    commands.entity(fog_tilemap_entity).insert((
+    // Tile centers sit half a tile in from the origin, like the tiles of the `FogGrid`
+    let fog_origin = (level.tile_size / 2.0).extend(1.0); // Render fog on top by adjusting z-axis
+    commands.entity(fog_tilemap_entity).insert(
         TilemapBundle {
             grid_size,
             map_type,
             size: map_size,
-            storage: fog_tile_storage.clone(),
+            storage: fog_tile_storage,
             texture: TilemapTexture::Single(textures.fog.clone()), // Use fog texture handle
             tile_size,
-            transform: Transform::from_translation(Vec3::new(360.0, 360.0, 1.0)), // Render fog on top by adjusting z-axis
+            transform: Transform::from_translation(fog_origin).with_scale(scale.extend(1.0)),
             ..Default::default()
 
         },
-        FogTile
-    ));
-
-
-    // Spawn the hive
-    let max_x = (map_size.x * tile_size.x as u32) - 720;
-    let max_y = (map_size.y * tile_size.y as u32) - 720;
-
-    let beehive_position = Vec3::new(
-        ((rand::random::<u32>() % max_x) as f32 / tile_size.x * tile_size.x + 360.) as f32,
-        ((rand::random::<u32>() % max_y) as f32 / tile_size.y * tile_size.y + 360.) as f32,
-        2.0,
     );
 
-    commands.spawn(SpriteBundle {
-        texture: textures.hive.clone(),
-        transform: Transform {
-            translation: beehive_position,
-            ..Default::default()
-        },
-        ..Default::default()
-    });
-    info!("Spawned beehive at {:?}", beehive_position);
+    let Some((hive_transform, _)) = q_hive.iter().find(|(_, team)| **team == Team::PLAYER) else {
+        return;
+    };
+    let beehive_position = hive_transform.translation;
     let mut camera_transform = q_camera.single_mut();
     camera_transform.translation = beehive_position;
+}
 
-    // Despawn fog around hive
-    // Calculate fog removal area around the beehive
-    let fog_removal_radius = 3; // Adjust the radius as needed
-    // Ensure fog removal area stays within the bounds of the tile storage
-    let fog_tile_storage_size = fog_tile_storage.size; // Assuming fog_tile_storage has a size() method
-    let beehive_tile_pos = TilePos {
-        x: (beehive_position.x / tile_size.x) as u32,
-        y: (beehive_position.y / tile_size.y) as u32,
-    };
-    let mut fog_removal_area = Vec::new();
-    for x in (beehive_tile_pos.x as i32 - fog_removal_radius as i32)..=(beehive_tile_pos.x as i32 + fog_removal_radius as i32) {
-        for y in (beehive_tile_pos.y as i32 - fog_removal_radius as i32)..=(beehive_tile_pos.y as i32 + fog_removal_radius as i32) {
-            if x >= 0 && y >= 0 && x < fog_tile_storage_size.x as i32 && y < fog_tile_storage_size.y as i32 {
-                fog_removal_area.push((x as u32, y as u32));
-            }
-        }
-    }
-    // Access the fog tile storage and remove fog tiles in the calculated area
-    for (x, y) in fog_removal_area {
-        if let Some(fog_tile_entity) = fog_tile_storage.get(&TilePos { x, y }) {
-            commands.entity(fog_tile_entity).despawn_recursive();
-        }
+// Gives the hive, queen and flowers their sprites once they have been generated
+fn attach_level_sprites(
+    mut commands: Commands,
+    textures: Res<TextureAssets>,
+    q_hives: Query<(Entity, &Transform, &Team), Added<Hive>>,
+    q_queens: Query<(Entity, &Transform, &Team), Added<Queen>>,
+    q_flowers: Query<(Entity, &Transform, &Flower), Added<Flower>>,
+) {
+    for (entity, transform, team) in q_hives.iter() {
+        commands.entity(entity).insert(SpriteBundle {
+            sprite: Sprite { color: team.color(), ..default() },
+            texture: textures.hive.clone(),
+            transform: *transform,
+            ..Default::default()
+        });
     }
-
-    // Spawn the Queen bee
-    // Calculate the maximum position bounds
-    let max_x = (map_size.x * tile_size.x as u32) - 720;
-    let max_y = (map_size.y * tile_size.y as u32) - 720;
-
-    // Spawn the bee queen away from the hive
-    let bee_queen_position = get_random_position_away_from_hive(beehive_position, max_x, max_y, grid_size, 720);
-    commands.spawn(SpriteBundle {
-        texture: textures.queen.clone(),
-        transform: Transform {
-            translation: bee_queen_position,
+    for (entity, transform, team) in q_queens.iter() {
+        commands.entity(entity).insert(SpriteBundle {
+            sprite: Sprite { color: team.color(), ..default() },
+            texture: textures.queen.clone(),
+            transform: *transform,
             ..Default::default()
-        },
-        ..Default::default()
-    });
-    info!("Spawned bee queen at {:?}", bee_queen_position);
-
-    // Spawn flowers randomly within the map bounds
-    const NUM_FLOWERS: usize = 20;
-    for _ in 0..NUM_FLOWERS {
-        let flower_type = rand::random::<u8>() % 4; // Assuming you have 4 flower types
-        let flower_texture = match flower_type {
+        });
+    }
+    for (entity, transform, flower) in q_flowers.iter() {
+        let flower_texture = match flower.kind {
             0 => textures.flower1.clone(),
             1 => textures.flower2.clone(),
             2 => textures.flower3.clone(),
             3 => textures.flower4.clone(),
             _ => textures.flower1.clone(), // Default to a texture if needed
         };
-
-        let flower_position = get_random_position(max_x, max_y, grid_size);
-
-        commands.spawn((
-            SpriteBundle {
-                texture: flower_texture,
-                transform: Transform {
-                    translation: flower_position,
-                    ..Default::default()
-                },
-                ..Default::default()
-            },
-            Flower
-        ));
+        commands.entity(entity).insert(SpriteBundle {
+            texture: flower_texture,
+            transform: *transform,
+            ..Default::default()
+        });
     }
+}
 
-    // Spawn the initial 5 bees
-    for _ in 0..5 {
-        let bee_body = if rng.gen::<bool>() {
-            textures.beebody1.clone()
-        } else {
-            textures.beebody2.clone()
-        };
-        let bee_offset_x = rng.gen_range(-500.0..=500.0); // Adjust the offset range as needed
-        let bee_offset_y = rng.gen_range(-500.0..=500.0);
-        let bee_entity = commands.spawn((
-            SpriteBundle {
-                texture: bee_body,
-                transform:  Transform::from_translation(Vec3::new(
-                    beehive_position.x + bee_offset_x,
-                    beehive_position.y + bee_offset_y,
-                    beehive_position.z,
-                )),
-                ..default()
-            },
-            Bee,
-            BeeBehavior::Destination(Vec2::new(beehive_position.x, beehive_position.y)),
-            Boid,
-            Highlightable,
-            Collider::new(25.0),
-            Velocity::default(),
-        )).id();
-
-        // Spawn the wings as a child of the bee body
-        let bee_wings= if rng.gen::<bool>() {
-            textures.bee1wingmap.clone()
-        } else {
-            textures.bee2wingmap.clone()
+// Fog tiles are never despawned, they fade in and out with the player's fog of war
+fn draw_fog(
+    fog: Res<FogOfWar>,
+    mut q_fog: Query<(&TilePos, &mut TileColor), With<FogTile>>,
+) {
+    let grid = fog.grid(Team::PLAYER);
+    for (tile_pos, mut color) in q_fog.iter_mut() {
+        let alpha = match grid.and_then(|grid| grid.get(tile_pos)) {
+            Some(FogState::Visible) => 0.0,
+            // Dimmed, the terrain shows through
+            Some(FogState::Explored) => 0.5,
+            _ => 1.0,
         };
-        commands.entity(bee_entity).with_children(|parent| {
-            parent.spawn(SpriteSheetBundle {
-                texture_atlas: bee_wings,
-                sprite: TextureAtlasSprite::new(0), // Set the initial sprite index
-                transform: Transform::from_xyz(0.0, 0.0, 1.0), // Adjust the position of the wings relative to the bee body
-                ..Default::default()
-            })
-                .insert(crate::bees::AnimationIndices { first: 0, last: 3 - 1 })
-                .insert(crate::bees::AnimationTimer(Timer::from_seconds(0.3, TimerMode::Repeating)));
-        });
-
-        // info!("Bee spawned at hive position");
+        if color.0.a() != alpha {
+            color.0.set_a(alpha);
+        }
     }
+}
 
+// Flowers and enemies are only drawn while the player can see them
+fn hide_unspotted(
+    mut q_entities: Query<(&mut Visibility, Option<&Team>, Has<Spotted>), Or<(With<Flower>, With<Team>)>>,
+) {
+    for (mut visibility, team, spotted) in q_entities.iter_mut() {
+        if team == Some(&Team::PLAYER) {
+            continue;
+        }
+        let wanted = if spotted { Visibility::Inherited } else { Visibility::Hidden };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
 }
 
 // Define a function to get a random position away from the hive
-fn get_random_position_away_from_hive(hive_position: Vec3, max_x: u32, max_y: u32, tile_size: TilemapGridSize, radius: u32) -> Vec3 {
+fn get_random_position_away_from_hive(rng: &mut impl Rng, hive_position: Vec3, max_x: u32, max_y: u32, tile_size: TilemapGridSize, radius: u32) -> Vec3 {
     loop {
-        let x = (rand::random::<u32>() % max_x) as f32 / tile_size.x * tile_size.x + 360.;
-        let y = (rand::random::<u32>() % max_y) as f32 / tile_size.y * tile_size.y + 360.;
+        let x = (rng.gen::<u32>() % max_x) as f32 / tile_size.x * tile_size.x + 360.;
+        let y = (rng.gen::<u32>() % max_y) as f32 / tile_size.y * tile_size.y + 360.;
         let candidate_position = Vec3::new(x, y, 1.0);
 
         // Check if the candidate position is away from the hive by the given radius
@@ -276,14 +303,57 @@ fn get_random_position_away_from_hive(hive_position: Vec3, max_x: u32, max_y: u3
 }
 
 // Define a function to get a random position within the map bounds
-fn get_random_position(max_x: u32, max_y: u32, tile_size: TilemapGridSize) -> Vec3 {
-    let x = (rand::random::<u32>() % max_x) as f32 / tile_size.x * tile_size.x + 360.;
-    let y = (rand::random::<u32>() % max_y) as f32 / tile_size.y * tile_size.y + 360.;
+fn get_random_position(rng: &mut impl Rng, max_x: u32, max_y: u32, tile_size: TilemapGridSize) -> Vec3 {
+    let x = (rng.gen::<u32>() % max_x) as f32 / tile_size.x * tile_size.x + 360.;
+    let y = (rng.gen::<u32>() % max_y) as f32 / tile_size.y * tile_size.y + 360.;
     Vec3::new(x, y, 1.0)
 }
 
 #[derive(Component)]
-pub struct Flower;
+pub struct Flower {
+    pub kind: u8, // Which of the 4 flower textures to draw
+    pub pollen: f32,
+    pub capacity: f32,
+    pub regen: f32, // Pollen grown back per second
+}
+
+impl Flower {
+    pub fn new(kind: u8) -> Self {
+        Flower { kind, pollen: 100.0, capacity: 100.0, regen: 1.0 }
+    }
+}
 
+// A single tile of the fog tilemap
 #[derive(Component)]
 pub struct FogTile;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::boids::Position;
+
+    // Everything `generate_level` spawns, with where it was spawned
+    fn generate(seed: u64) -> Vec<(Entity, Vec3, Option<Vec3>)> {
+        let mut app = App::new();
+        app.insert_resource(GameSeed(seed))
+            .init_resource::<Difficulty>()
+            .init_resource::<Castes>()
+            .add_systems(Startup, generate_level);
+        app.update();
+        let mut spawned = app.world.query::<(Entity, &Transform, Option<&Position>)>();
+        let mut spawned: Vec<_> = spawned
+            .iter(&app.world)
+            .map(|(entity, transform, position)| (entity, transform.translation, position.map(|p| p.0)))
+            .collect();
+        spawned.sort_by_key(|(entity, _, _)| *entity);
+        spawned
+    }
+
+    #[test]
+    fn same_seed_same_level() {
+        let level = generate(42);
+        assert!(!level.is_empty());
+        assert_eq!(level, generate(42));
+        assert_ne!(level, generate(43));
+    }
+}
@@ -1,13 +1,22 @@
 use crate::GameState;
 use bevy::prelude::*;
+use bevy_ecs_ldtk::ldtk::LayerInstance;
 use bevy_ecs_ldtk::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
+use crate::ai::Difficulty;
+use crate::bees::Team;
+use crate::caste::Castes;
+use crate::level::{populate_level, LevelData, LevelLayout};
 use crate::menu::NextLevel;
+use crate::simulation::GameSeed;
 
 pub struct WorldPlugin;
 
-// This plugin is responsible for spawning the LDtk game world and entities
-// The world is only spawned during the State `GameState::Playing` and is removed when that state is exited
+// This plugin is responsible for spawning the LDtk game world picked in the menu, and turning it into a level
+// The world is only spawned during the State `GameState::Playing`, generated levels are left to the `MapPlugin`
 
 impl Plugin for WorldPlugin {
     fn build(&self, app: &mut App) {
@@ -15,85 +24,149 @@ impl Plugin for WorldPlugin {
             // LDtk level selection resource
             .insert_resource(LevelSelection::index(0))
             .init_resource::<LdtkLevel>()
-            .add_systems(OnEnter(GameState::Playing), setup_level)
-            .insert_resource(LevelData { level_height: 0, level_width: 0 })
-            .add_systems(Update, get_level_data.run_if(in_state(GameState::Playing)))
-            .add_plugins(LdtkPlugin)
-            // Register LDtk entities
-            .register_ldtk_entity::<QueenBundle>("Queen")
-            .register_ldtk_entity::<EnemyQueenBundle>("EnemyQueen");
+            .add_systems(OnEnter(GameState::Playing), setup_level.run_if(ldtk_level))
+            .add_systems(
+                Update,
+                load_level.run_if(
+                    in_state(GameState::Playing)
+                        .and_then(ldtk_level)
+                        .and_then(not(resource_exists::<LevelData>()))
+                        .and_then(not(resource_exists::<UnplayableLevel>())),
+                ),
+            )
+            .add_plugins(LdtkPlugin);
     }
 }
 
+// World units per LDtk pixel, so LDtk levels play at about the scale of generated ones
+pub const LDTK_SCALE: f32 = 10.0;
+// IntGrid value that bees have to fly around, in any layer
+const WALL_IDENTIFIER: &str = "Wall";
+
 #[derive(Resource, Default)]
 pub struct LdtkLevel(pub Handle<LdtkProject>);
 
+// The picked LDtk level turned out to have nothing to play, so `load_level` stops trying until the next one
+#[derive(Resource, Debug)]
+pub struct UnplayableLevel;
+
+fn ldtk_level(next_level: Res<NextLevel>) -> bool {
+    next_level.0.is_some()
+}
+
 fn setup_level(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     level: Res<NextLevel>,
-    mut camera: Query<(&mut Transform, &mut OrthographicProjection), With<Camera2d>>,
 ) {
-    // Change camera settings on playing state
-    info!("Change camera settings on playing state");
-    for (mut transform, mut projection) in &mut camera {
-        // Set world camera scale and location
-        projection.scale = 0.25;
-        transform.translation.x = 30.0;
-        transform.translation.y = 30.0;
-    }
-
-    let level_handle = asset_server.load(level.0);
+    let Some(level) = level.0 else {
+        return;
+    };
+    let level_handle = asset_server.load(level);
 
     commands.insert_resource(LdtkLevel(level_handle.clone()));
+    commands.remove_resource::<UnplayableLevel>();
 
     // Spawn LDTK level
-    info!("Spawn LDTK level");
+    info!("Spawn LDTK level {}", level);
     commands.spawn(LdtkWorldBundle {
         ldtk_handle: level_handle,
+        // Layers keep their own depth, under the fog and everything on the level
+        transform: Transform::from_scale(Vec3::new(LDTK_SCALE, LDTK_SCALE, 1.0)),
         ..Default::default()
     });
 }
 
-#[derive(Resource, Debug)]
-pub struct LevelData {
-    pub level_height: i32,
-    pub level_width: i32,
-}
-
-fn get_level_data(
-    level: Res<Assets<LdtkProject>>,
-    // mut camera: Query<Camera>,
+// Turns the level picked by the `LevelSelection` into a `LevelLayout` once it is loaded, populated like a generated
+// one. Only root levels are looked at, projects with several worlds aren't supported
+// Walls are the IntGrid values named `Wall`, hives, queens and flowers the entities of the same names
+fn load_level(
+    mut commands: Commands,
+    projects: Res<Assets<LdtkProject>>,
     handle: Res<LdtkLevel>,
-    mut loaded: Local<bool>,
-    mut level_data: ResMut<LevelData>,
+    selection: Res<LevelSelection>,
+    seed: Res<GameSeed>,
+    difficulty: Res<Difficulty>,
+    castes: Res<Castes>,
 ) {
-    // get the level of a handle
-    if *loaded {
+    let Some(project) = projects.get(&handle.0) else {
         return;
-    }
-
-    // let mut pancam = camera.single_mut();
-    if let Some(data) = level.get(&handle.0) {
-        level_data.level_height = data.iter_root_levels().next().unwrap().px_hei;
-        level_data.level_width = data.iter_root_levels().next().unwrap().px_wid;
+    };
+    let Some((_, level)) = project
+        .iter_root_levels()
+        .enumerate()
+        .find(|(index, level)| selection.is_match(&LevelIndices::in_root(*index), level))
+    else {
+        warn!("The LDtk project has no level matching {:?}", *selection);
+        commands.insert_resource(UnplayableLevel);
+        return;
+    };
+    let layers = level.layer_instances.as_deref().unwrap_or_default();
+    // The IntGrid layer sets the tiles of the level
+    let Some(ground) = layers.iter().find(|layer| !layer.int_grid_csv.is_empty()) else {
+        warn!("LDtk level {} has no IntGrid layer", level.identifier);
+        commands.insert_resource(UnplayableLevel);
+        return;
+    };
+    let map_size = TilemapSize { x: ground.c_wid as u32, y: ground.c_hei as u32 };
+    let level_data = LevelData::new(map_size, Vec2::splat(ground.grid_size as f32 * LDTK_SCALE));
+    // LDtk counts pixels down from the top left
+    let to_world = |px: IVec2| Vec2::new(px.x as f32, (level.px_hei - px.y) as f32) * LDTK_SCALE;
+    // Every ground tile a cell of some layer covers, going through pixels as layers can have other grid sizes
+    // and offsets than the ground. `TilePos` rows count up from the bottom
+    let ground_tiles = |layer: &LayerInstance, x: i32, y: i32| {
+        let min = IVec2::new(x, y) * layer.grid_size + IVec2::new(layer.px_total_offset_x, layer.px_total_offset_y)
+            - IVec2::new(ground.px_total_offset_x, ground.px_total_offset_y);
+        let max = min + layer.grid_size;
+        let first = min.div_euclid(IVec2::splat(ground.grid_size)).max(IVec2::ZERO);
+        let last = (max - 1).div_euclid(IVec2::splat(ground.grid_size)).min(IVec2::new(ground.c_wid - 1, ground.c_hei - 1));
+        let rows = ground.c_hei;
+        (first.y..=last.y)
+            .flat_map(move |row| (first.x..=last.x).map(move |column| (column, row)))
+            .map(move |(column, row)| TilePos { x: column as u32, y: (rows - 1 - row) as u32 })
+    };
 
-        *loaded = true;
+    let mut rng = StdRng::seed_from_u64(seed.0);
+    let mut layout = LevelLayout::default();
+    for layer in layers {
+        let walls: Vec<i32> = project
+            .json_data()
+            .defs
+            .layers
+            .iter()
+            .filter(|definition| definition.identifier == layer.identifier)
+            .flat_map(|definition| definition.int_grid_values.iter())
+            .filter(|value| value.identifier.as_deref() == Some(WALL_IDENTIFIER))
+            .map(|value| value.value)
+            .collect();
+        for (index, value) in layer.int_grid_csv.iter().enumerate() {
+            if !walls.contains(value) {
+                continue;
+            }
+            let (x, y) = (index as i32 % layer.c_wid, index as i32 / layer.c_wid);
+            layout.walls.extend(ground_tiles(layer, x, y));
+        }
+        for entity in layer.entity_instances.iter() {
+            let position = to_world(entity.px);
+            match entity.identifier.as_str() {
+                "Hive" => layout.hives.push((position, Team::PLAYER)),
+                "EnemyHive" => layout.hives.push((position, Team::ENEMY)),
+                "Queen" => layout.queens.push((position, Team::PLAYER)),
+                "EnemyQueen" => layout.queens.push((position, Team::ENEMY)),
+                "Flower" => layout.flowers.push((position, rng.gen::<u8>() % 4)),
+                other => warn!("Don't know what to spawn for LDtk entity {}", other),
+            }
+        }
     }
-}
-
-#[derive(Default, Component)]
-pub struct Queen;
-// Spawning sprites for LDtk entities
-#[derive(Default, Bundle, LdtkEntity)]
-struct QueenBundle {
-    queen: Queen,
-    #[sprite_sheet_bundle]
-    sprite_sheet_bundle: SpriteSheetBundle,
-}
-
-#[derive(Default, Bundle, LdtkEntity)]
-struct EnemyQueenBundle {
-    #[sprite_sheet_bundle]
-    sprite_sheet_bundle: SpriteSheetBundle,
+    info!(
+        "Loaded LDtk level {}: {}x{} tiles, {} walls, {} hives, {} queens, {} flowers",
+        level.identifier,
+        map_size.x,
+        map_size.y,
+        layout.walls.len(),
+        layout.hives.len(),
+        layout.queens.len(),
+        layout.flowers.len()
+    );
+    populate_level(&mut commands, level_data, layout, rng, &difficulty, &castes);
 }